@@ -1,8 +1,10 @@
 use criterion::{criterion_group, criterion_main, Criterion, black_box};
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use rts_assignment::{
-    structs::{Order, Inventory},
+    address::Address,
+    structs::{Control, Order, OrderLine, OrderStatus},
     functions::{
         generate_orders,
         process_payment,
@@ -11,41 +13,25 @@ use rts_assignment::{
         repayment,
         redelivery,
         handle_return,
+        receive_orders,
+        Tagged,
+        send_control,
         send_queue,
     },
+    backorder::BackorderStore,
+    payment::RandomGateway,
+    config::{self, InventoryConfig},
+    dispatch::DispatchBoard,
+    replenishment::StockoutPolicy,
+    routing::RouteTable,
+    warehouse::{Allocation, Warehouses},
+    transport::InMemoryTransport,
 };
 
 const ORDER_LIMIT: i32 = 10;
 
-// Mock function to simulate receiving messages from the queue
-fn recv_msg_mock(_queue_name: &str) -> String {
-    let order = Order {
-        id: 1,
-        item: "T-Shirt".to_string(),
-        quantity: 1,
-        shipping_address: "Johor".to_string(),
-        payment_status: false,
-        delivery_status: false,
-        final_status: "Pending".to_string(),
-    };
-    serde_json::to_string(&order).unwrap()
-}
-
-// Updated receive_orders function using the mock with limited iterations
-fn receive_orders_mock(queue_name: &str, sender: Sender<Order>, max_iterations: usize) {
-    for _ in 0..max_iterations {
-        let order = recv_msg_mock(queue_name);
-        if !order.is_empty() {
-            if let Ok(deserialized_order) = serde_json::from_str::<Order>(&order) {
-                if deserialized_order.id == -1 {
-                    sender.send(deserialized_order).unwrap();
-                    break;
-                } else {
-                    sender.send(deserialized_order).unwrap();
-                }
-            }
-        }
-    }
+fn mock_address() -> Address {
+    Address::new("Nur Aisyah", &["12 Jalan Merdeka"], "80100", "Johor Bahru", "Johor")
 }
 
 fn benchmark_generate_orders(c: &mut Criterion) {
@@ -54,7 +40,7 @@ fn benchmark_generate_orders(c: &mut Criterion) {
     group.bench_function("generate_orders", |b| {
         b.iter(|| {
             let (order_tx, _order_rx): (Sender<Order>, _) = channel();
-            generate_orders(black_box(&order_tx), black_box(ORDER_LIMIT));
+            generate_orders(black_box(&order_tx), black_box(1..=ORDER_LIMIT), Duration::from_secs(1));
         })
     });
     group.finish();
@@ -63,18 +49,11 @@ fn benchmark_generate_orders(c: &mut Criterion) {
 fn benchmark_process_payment(c: &mut Criterion) {
     let mut group = c.benchmark_group("Process Payment");
     group.sample_size(100);  // Attempt to get closer to 100 iterations
+    let gateway = RandomGateway::new(0.5, Some(42));
     group.bench_function("process_payment", |b| {
         b.iter(|| {
-            let mut order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: false,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            process_payment(black_box(&mut order));
+            let mut order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            process_payment(&InMemoryTransport::new(), &gateway, black_box(&mut order)).unwrap();
         })
     });
     group.finish();
@@ -83,19 +62,13 @@ fn benchmark_process_payment(c: &mut Criterion) {
 fn benchmark_inventory_checking(c: &mut Criterion) {
     let mut group = c.benchmark_group("Inventory Checking");
     group.sample_size(100);  // Attempt to get closer to 100 iterations
+    let settings = InventoryConfig { stockout: StockoutPolicy::Fail, ..Default::default() };
     group.bench_function("inventory_checking", |b| {
         b.iter(|| {
-            let inventory = Arc::new(Mutex::new(Inventory::new()));
-            let mut order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: false,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            inventory_checking(black_box(&inventory), black_box(&mut order));
+            let inventory = Arc::new(Mutex::new(Warehouses::new()));
+            let mut order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            order.status = OrderStatus::Paid;
+            inventory_checking(&InMemoryTransport::new(), black_box(&inventory), black_box(&mut order), &settings, &mut BackorderStore::default()).unwrap();
         })
     });
     group.finish();
@@ -104,18 +77,13 @@ fn benchmark_inventory_checking(c: &mut Criterion) {
 fn benchmark_process_delivery(c: &mut Criterion) {
     let mut group = c.benchmark_group("Process Delivery");
     group.sample_size(100);  // Attempt to get closer to 100 iterations
+    let routes = RouteTable::default();
     group.bench_function("process_delivery", |b| {
         b.iter(|| {
-            let mut order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: true,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            process_delivery(black_box(&mut order));
+            let mut order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            order.status = OrderStatus::Reserved;
+            let mut board = DispatchBoard::new(&config::get().shipping.couriers);
+            process_delivery(&InMemoryTransport::new(), &routes, &mut board, black_box(&mut order)).unwrap();
         })
     });
     group.finish();
@@ -124,18 +92,12 @@ fn benchmark_process_delivery(c: &mut Criterion) {
 fn benchmark_repayment(c: &mut Criterion) {
     let mut group = c.benchmark_group("Repayment");
     group.sample_size(100);  // Attempt to get closer to 100 iterations
+    let gateway = RandomGateway::new(0.5, Some(42));
     group.bench_function("repayment", |b| {
         b.iter(|| {
-            let mut order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: false,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            repayment(black_box(&mut order));
+            let mut order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            order.status = OrderStatus::PaymentFailed;
+            repayment(&InMemoryTransport::new(), &gateway, black_box(&mut order)).unwrap();
         })
     });
     group.finish();
@@ -146,16 +108,9 @@ fn benchmark_redelivery(c: &mut Criterion) {
     group.sample_size(100);  // Attempt to get closer to 100 iterations
     group.bench_function("redelivery", |b| {
         b.iter(|| {
-            let mut order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: true,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            redelivery(black_box(&mut order));
+            let mut order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            order.status = OrderStatus::DeliveryFailed;
+            redelivery(&InMemoryTransport::new(), black_box(&mut order)).unwrap();
         })
     });
     group.finish();
@@ -166,17 +121,11 @@ fn benchmark_handle_return(c: &mut Criterion) {
     group.sample_size(100);  // Attempt to get closer to 100 iterations
     group.bench_function("handle_return", |b| {
         b.iter(|| {
-            let inventory = Arc::new(Mutex::new(Inventory::new()));
-            let return_order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: false,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            handle_return(black_box(&inventory), black_box(&return_order));
+            let inventory = Arc::new(Mutex::new(Warehouses::new()));
+            let allocation = Allocation { warehouse: "Shah Alam".to_string(), sku: "TS-001".to_string(), quantity: 1 };
+            inventory.lock().unwrap().reserve(1, &[allocation]).unwrap();
+            let return_order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            handle_return(black_box(&inventory), black_box(&return_order)).unwrap();
         })
     });
     group.finish();
@@ -187,16 +136,8 @@ fn benchmark_send_queue(c: &mut Criterion) {
     group.sample_size(100);  // Attempt to get closer to 100 iterations
     group.bench_function("send_queue", |b| {
         b.iter(|| {
-            let order = Order {
-                id: 1,
-                item: "T-Shirt".to_string(),
-                quantity: 1,
-                shipping_address: "Johor".to_string(),
-                payment_status: false,
-                delivery_status: false,
-                final_status: "Pending".to_string(),
-            };
-            send_queue(black_box(&order), black_box("payment"));
+            let order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            send_queue(&InMemoryTransport::new(), black_box(&order), black_box("payment")).unwrap();
         })
     });
     group.finish();
@@ -208,10 +149,17 @@ fn benchmark_receive_orders(c: &mut Criterion) {
 
     group.bench_function("receive_orders", |b| {
         b.iter(|| {
-            let (order_tx, order_rx): (Sender<Order>, Receiver<Order>) = channel();
+            let (order_tx, order_rx): (Sender<Tagged>, Receiver<Tagged>) = channel();
+            let transport = Arc::new(InMemoryTransport::new());
+
+            // Queue up orders followed by a shutdown so receive_orders returns
+            let order = Order::new(1, vec![OrderLine::new("TS-001", 1)], mock_address());
+            for _ in 0..10 {
+                send_queue(transport.as_ref(), &order, "payment").unwrap();
+            }
+            send_control(transport.as_ref(), Control::Shutdown, "payment").unwrap();
 
-            // Simulate receiving orders from the queue with limited iterations
-            receive_orders_mock(black_box("payment_queue"), black_box(order_tx.clone()), 10);
+            receive_orders(transport, black_box("payment_queue"), 1, black_box(order_tx.clone())).unwrap();
 
             // Process messages from the receiver end
             while let Ok(_order) = order_rx.try_recv() {
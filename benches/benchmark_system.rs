@@ -3,7 +3,8 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use rts_assignment::{
-    structs::{Order, Inventory},
+    address::Address,
+    structs::{Control, Envelope, Order, OrderLine, OrderStatus},
     functions::{
         process_payment,
         inventory_checking,
@@ -11,39 +12,34 @@ use rts_assignment::{
         repayment,
         redelivery,
         handle_return,
-        send_queue,
+        send_control,
     },
+    backorder::BackorderStore,
+    payment::RandomGateway,
+    config::{self, InventoryConfig},
+    dispatch::DispatchBoard,
+    replenishment::StockoutPolicy,
+    routing::RouteTable,
+    warehouse::Warehouses,
+    transport::{InMemoryTransport, MessageTransport},
 };
 
 const MAX_ITERATIONS: usize = 100;  // Limit the number of iterations for benchmarking
 
 fn create_mock_order(id: i32) -> Order {
-    Order {
-        id,
-        item: "T-Shirt".to_string(),
-        quantity: 1,
-        shipping_address: "Johor".to_string(),
-        payment_status: false,
-        delivery_status: false,
-        final_status: "Pending".to_string(),
-    }
+    let address = Address::new("Nur Aisyah", &["12 Jalan Merdeka"], "80100", "Johor Bahru", "Johor");
+    Order::new(id, vec![OrderLine::new("TS-001", 1)], address)
 }
 
-fn receive_orders_benchmark(queue_name: &str, sender: Sender<Order>, max_iterations: usize) {
+// Feed a stage with mock orders already in the status that stage expects
+fn receive_orders_benchmark(_queue_name: &str, sender: Sender<Envelope<Order>>, max_iterations: usize, status: OrderStatus) {
     for i in 0..max_iterations {
-        let order = create_mock_order(i as i32);
-        sender.send(order).unwrap();
+        let mut order = create_mock_order(i as i32);
+        order.status = status;
+        sender.send(Envelope::Message(order)).unwrap();
     }
-    // Send a termination order
-    sender.send(Order {
-        id: -1,
-        item: String::new(),
-        quantity: 0,
-        shipping_address: String::new(),
-        payment_status: false,
-        delivery_status: false,
-        final_status: String::new(),
-    }).unwrap();
+    // Send a termination signal
+    sender.send(Envelope::Control(Control::Shutdown)).unwrap();
 }
 
 fn benchmark_system(c: &mut Criterion) {
@@ -51,100 +47,118 @@ fn benchmark_system(c: &mut Criterion) {
 
     group.bench_function("whole_system", |b| {
         b.iter(|| {
-            let (order_tx, order_rx): (Sender<Order>, Receiver<Order>) = channel();
-            let (payment_tx, payment_rx): (Sender<Order>, Receiver<Order>) = channel();
-            let (inventory_tx, inventory_rx): (Sender<Order>, Receiver<Order>) = channel();
-            let (return_tx, return_rx): (Sender<Order>, Receiver<Order>) = channel();
-            let (delivery_tx, delivery_rx): (Sender<Order>, Receiver<Order>) = channel();
-            let (monitor_tx, monitor_rx): (Sender<Order>, Receiver<Order>) = channel();
-            let (database_tx, database_rx): (Sender<Order>, Receiver<Order>) = channel();
+            let (order_tx, _order_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let (payment_tx, payment_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let (inventory_tx, inventory_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let (return_tx, return_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let (delivery_tx, delivery_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let (monitor_tx, monitor_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let (database_tx, database_rx): (Sender<Envelope<Order>>, Receiver<Envelope<Order>>) = channel();
+            let transport: Arc<dyn MessageTransport> = Arc::new(InMemoryTransport::new());
 
             // Start the order generation thread
             let order_thread = thread::spawn(move || {
                 for i in 0..MAX_ITERATIONS {
-                    order_tx.send(create_mock_order(i as i32)).unwrap();
+                    order_tx.send(Envelope::Message(create_mock_order(i as i32))).unwrap();
                 }
                 // Send termination signal
-                order_tx.send(Order {
-                    id: -1,
-                    item: String::new(),
-                    quantity: 0,
-                    shipping_address: String::new(),
-                    payment_status: false,
-                    delivery_status: false,
-                    final_status: String::new(),
-                }).unwrap();
+                order_tx.send(Envelope::Control(Control::Shutdown)).unwrap();
             });
 
             // Start the payment system thread
+            let payment_transport = Arc::clone(&transport);
             let payment_thread = thread::spawn(move || {
-                receive_orders_benchmark("payment_queue", payment_tx.clone(), MAX_ITERATIONS);
-                while let Ok(mut order) = payment_rx.recv() {
-                    if order.id == -1 {
-                        send_queue(&order, "inventory");
-                        println!("Shutting down the payment system...");
-                        break;
-                    }
-                    process_payment(&mut order);
+                let gateway = RandomGateway::new(0.5, None);
+                receive_orders_benchmark("payment_queue", payment_tx.clone(), MAX_ITERATIONS, OrderStatus::Pending);
+                while let Ok(envelope) = payment_rx.recv() {
+                    let mut order = match envelope {
+                        Envelope::Message(order) => order,
+                        Envelope::Control(_) => {
+                            send_control(payment_transport.as_ref(), Control::Shutdown, "inventory").unwrap();
+                            println!("Shutting down the payment system...");
+                            break;
+                        }
+                    };
+                    process_payment(payment_transport.as_ref(), &gateway, &mut order).unwrap();
                 }
             });
 
             // Start the inventory system thread
-            let inventory = Arc::new(Mutex::new(Inventory::new()));
+            let inventory = Arc::new(Mutex::new(Warehouses::new()));
             let inventory_clone = Arc::clone(&inventory);
+            let inventory_transport = Arc::clone(&transport);
             let inventory_thread = thread::spawn(move || {
-                receive_orders_benchmark("inventory_queue", inventory_tx.clone(), MAX_ITERATIONS);
-                while let Ok(mut order) = inventory_rx.recv() {
-                    if order.id == -1 {
-                        send_queue(&order, "return");
-                        println!("Shutting down the inventory system...");
-                        break;
-                    }
-                    inventory_checking(&inventory_clone, &mut order);
+                receive_orders_benchmark("inventory_queue", inventory_tx.clone(), MAX_ITERATIONS, OrderStatus::Paid);
+                let settings = InventoryConfig { stockout: StockoutPolicy::Fail, ..Default::default() };
+                while let Ok(envelope) = inventory_rx.recv() {
+                    let mut order = match envelope {
+                        Envelope::Message(order) => order,
+                        Envelope::Control(_) => {
+                            send_control(inventory_transport.as_ref(), Control::Shutdown, "return_inventory").unwrap();
+                            println!("Shutting down the inventory system...");
+                            break;
+                        }
+                    };
+                    inventory_checking(inventory_transport.as_ref(), &inventory_clone, &mut order, &settings, &mut BackorderStore::default()).unwrap();
                 }
             });
 
             // Start the return inventory system thread
             let inventory_clone_return = Arc::clone(&inventory);
+            let return_transport = Arc::clone(&transport);
             let return_thread = thread::spawn(move || {
-                receive_orders_benchmark("return_inventory_queue", return_tx.clone(), MAX_ITERATIONS);
-                while let Ok(return_order) = return_rx.recv() {
-                    if return_order.id == -1 {
-                        send_queue(&return_order, "delivery");
-                        println!("Shutting down the return inventory system...");
-                        break;
-                    }
-                    handle_return(&inventory_clone_return, &return_order);
+                receive_orders_benchmark("return_inventory_queue", return_tx.clone(), MAX_ITERATIONS, OrderStatus::Cancelled);
+                while let Ok(envelope) = return_rx.recv() {
+                    let return_order = match envelope {
+                        Envelope::Message(return_order) => return_order,
+                        Envelope::Control(_) => {
+                            send_control(return_transport.as_ref(), Control::Shutdown, "delivery").unwrap();
+                            println!("Shutting down the return inventory system...");
+                            break;
+                        }
+                    };
+                    handle_return(&inventory_clone_return, &return_order).unwrap();
                 }
             });
 
             // Start the delivery system thread
+            let delivery_transport = Arc::clone(&transport);
             let delivery_thread = thread::spawn(move || {
-                receive_orders_benchmark("delivery_queue", delivery_tx.clone(), MAX_ITERATIONS);
-                while let Ok(mut order) = delivery_rx.recv() {
-                    if order.id == -1 {
-                        send_queue(&order, "monitor");
-                        println!("Shutting down the delivery system...");
-                        break;
-                    }
-                    process_delivery(&mut order);
+                receive_orders_benchmark("delivery_queue", delivery_tx.clone(), MAX_ITERATIONS, OrderStatus::Reserved);
+                let routes = RouteTable::default();
+                let mut board = DispatchBoard::new(&config::get().shipping.couriers);
+                while let Ok(envelope) = delivery_rx.recv() {
+                    let mut order = match envelope {
+                        Envelope::Message(order) => order,
+                        Envelope::Control(_) => {
+                            send_control(delivery_transport.as_ref(), Control::Shutdown, "monitoring").unwrap();
+                            println!("Shutting down the delivery system...");
+                            break;
+                        }
+                    };
+                    process_delivery(delivery_transport.as_ref(), &routes, &mut board, &mut order).unwrap();
                 }
             });
 
             // Start the monitor system thread
+            let monitor_transport = Arc::clone(&transport);
             let monitor_thread = thread::spawn(move || {
-                receive_orders_benchmark("monitor_queue", monitor_tx.clone(), MAX_ITERATIONS);
-                while let Ok(mut order) = monitor_rx.recv() {
-                    if order.id == -1 {
-                        send_queue(&order, "database");
-                        println!("Shutting down the monitor system...");
-                        break;
-                    }
+                let gateway = RandomGateway::new(0.5, None);
+                receive_orders_benchmark("monitor_queue", monitor_tx.clone(), MAX_ITERATIONS, OrderStatus::PaymentFailed);
+                while let Ok(envelope) = monitor_rx.recv() {
+                    let mut order = match envelope {
+                        Envelope::Message(order) => order,
+                        Envelope::Control(_) => {
+                            send_control(monitor_transport.as_ref(), Control::Shutdown, "database").unwrap();
+                            println!("Shutting down the monitor system...");
+                            break;
+                        }
+                    };
                     println!("[Order ID {}] Monitoring system received order", order.id);
-                    if !order.payment_status {
-                        repayment(&mut order);
-                    } else if order.payment_status && !order.delivery_status {
-                        redelivery(&mut order);
+                    if order.status == OrderStatus::PaymentFailed {
+                        repayment(monitor_transport.as_ref(), &gateway, &mut order).unwrap();
+                    } else if order.status == OrderStatus::DeliveryFailed {
+                        redelivery(monitor_transport.as_ref(), &mut order).unwrap();
                     }
                     println!("------------------------------------------------------------")
                 }
@@ -152,15 +166,18 @@ fn benchmark_system(c: &mut Criterion) {
 
             // Start the database system thread
             let database_thread = thread::spawn(move || {
-                receive_orders_benchmark("database_queue", database_tx.clone(), MAX_ITERATIONS);
-                while let Ok(order) = database_rx.recv() {
-                    if order.id == -1 {
-                        println!("Shutting down the database system...");
-                        break;
-                    }
+                receive_orders_benchmark("database_queue", database_tx.clone(), MAX_ITERATIONS, OrderStatus::Delivered);
+                while let Ok(envelope) = database_rx.recv() {
+                    let order = match envelope {
+                        Envelope::Message(order) => order,
+                        Envelope::Control(_) => {
+                            println!("Shutting down the database system...");
+                            break;
+                        }
+                    };
                     println!(
-                        "Order ID: {}, Item: {}, Quantity: {}, Shipping Address: {}, Final Status: {}",
-                        order.id, order.item, order.quantity, order.shipping_address, order.final_status
+                        "Order ID: {}, Items: {}, Shipping Address: {}, Final Status: {}",
+                        order.id, order.describe_lines(), order.shipping_address, order.status
                     );
                     println!("---------------------------------------------------------------------------------------------")
                 }
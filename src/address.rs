@@ -0,0 +1,203 @@
+use std::fmt;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+// Orders only ship within Malaysia
+pub const COUNTRY: &str = "Malaysia";
+
+// Postcodes each state uses, as inclusive ranges
+const POSTCODE_RANGES: [(&str, &[(u32, u32)]); 16] = [
+    ("Perlis", &[(1000, 2800)]),
+    ("Kedah", &[(5000, 9810)]),
+    ("Penang", &[(10000, 14400)]),
+    ("Kelantan", &[(15000, 18500)]),
+    ("Terengganu", &[(20000, 24300)]),
+    ("Pahang", &[(25000, 28800), (39000, 39200), (49000, 49000), (69000, 69000)]),
+    ("Perak", &[(30000, 36810)]),
+    ("Selangor", &[(40000, 48300), (63000, 68100)]),
+    ("Kuala Lumpur", &[(50000, 60000)]),
+    ("Putrajaya", &[(62000, 62988)]),
+    ("Negeri Sembilan", &[(70000, 73509)]),
+    ("Melaka", &[(75000, 78309)]),
+    ("Johor", &[(79000, 86900)]),
+    ("Labuan", &[(87000, 87033)]),
+    ("Sabah", &[(88000, 91309)]),
+    ("Sarawak", &[(93000, 98859)]),
+];
+
+// Whether `name` is a Malaysian state or federal territory, ignoring case
+pub fn is_state(name: &str) -> bool {
+    POSTCODE_RANGES.iter().any(|(state, _)| state.eq_ignore_ascii_case(name.trim()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Address {
+    pub recipient: String,
+    // Street lines, most specific first
+    pub lines: Vec<String>,
+    pub postcode: String,
+    pub city: String,
+    pub state: String,
+    pub country: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressError {
+    // A required field is empty
+    Missing(&'static str),
+    UnsupportedCountry(String),
+    UnknownState(String),
+    // Not five digits
+    InvalidPostcode(String),
+    PostcodeOutsideState { postcode: String, state: String },
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::Missing(field) => write!(f, "the address has no {}", field),
+            AddressError::UnsupportedCountry(country) => write!(f, "orders only ship within {}, not to {}", COUNTRY, country),
+            AddressError::UnknownState(state) => write!(f, "{} is not a Malaysian state or federal territory", state),
+            AddressError::InvalidPostcode(postcode) => write!(f, "postcode {} is not five digits", postcode),
+            AddressError::PostcodeOutsideState { postcode, state } => write!(f, "postcode {} is not in {}", postcode, state),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+impl Address {
+    // An address in Malaysia
+    pub fn new(recipient: &str, lines: &[&str], postcode: &str, city: &str, state: &str) -> Self {
+        Address {
+            recipient: recipient.to_string(),
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            postcode: postcode.to_string(),
+            city: city.to_string(),
+            state: state.to_string(),
+            country: COUNTRY.to_string(),
+        }
+    }
+
+    // Check the address can be shipped to, returning the state as the rest of the
+    // system spells it (e.g. "Kuala Lumpur" for "kuala lumpur")
+    pub fn validate(&self) -> Result<&'static str, AddressError> {
+        let required = [
+            ("recipient", self.recipient.as_str()),
+            ("street line", self.lines.first().map_or("", |line| line.as_str())),
+            ("city", self.city.as_str()),
+            ("state", self.state.as_str()),
+            ("country", self.country.as_str()),
+        ];
+        if let Some((field, _)) = required.iter().find(|(_, value)| value.trim().is_empty()) {
+            return Err(AddressError::Missing(field));
+        }
+        if !self.country.trim().eq_ignore_ascii_case(COUNTRY) {
+            return Err(AddressError::UnsupportedCountry(self.country.clone()));
+        }
+
+        let (state, ranges) = POSTCODE_RANGES
+            .iter()
+            .find(|(state, _)| state.eq_ignore_ascii_case(self.state.trim()))
+            .ok_or_else(|| AddressError::UnknownState(self.state.clone()))?;
+        let postcode = self.postcode.trim();
+        if postcode.len() != 5 || !postcode.chars().all(|c| c.is_ascii_digit()) {
+            return Err(AddressError::InvalidPostcode(self.postcode.clone()));
+        }
+        let number: u32 = postcode.parse().map_err(|_| AddressError::InvalidPostcode(self.postcode.clone()))?;
+        if !ranges.iter().any(|&(low, high)| (low..=high).contains(&number)) {
+            return Err(AddressError::PostcodeOutsideState { postcode: self.postcode.clone(), state: state.to_string() });
+        }
+        Ok(state)
+    }
+
+    // With a `percent` chance, replace the postcode with one from another state, as a
+    // customer mistyping it would. Returns whether the postcode was replaced.
+    pub fn mistype_postcode<R: Rng>(&mut self, rng: &mut R, percent: u32) -> bool {
+        if !rng.gen_ratio(percent.min(100), 100) {
+            return false;
+        }
+        let others: Vec<_> = POSTCODE_RANGES.iter().filter(|(state, _)| !state.eq_ignore_ascii_case(self.state.trim())).collect();
+        let (_, ranges) = others.choose(rng).expect("there is more than one state");
+        let &(low, high) = ranges.choose(rng).expect("every state has a postcode range");
+        self.postcode = format!("{:05}", rng.gen_range(low..=high));
+        true
+    }
+
+    // "Kota Kinabalu, Sabah"
+    pub fn locality(&self) -> String {
+        format!("{}, {}", self.city, self.state)
+    }
+}
+
+// "Nur Aisyah, 12 Jalan Ampang, 50450 Kuala Lumpur, Kuala Lumpur, Malaysia"
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, ", self.recipient)?;
+        for line in &self.lines {
+            write!(f, "{}, ", line)?;
+        }
+        write!(f, "{} {}, {}, {}", self.postcode, self.city, self.state, self.country)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn address(postcode: &str, state: &str) -> Address {
+        Address::new("Nur Aisyah", &["12 Jalan Ampang"], postcode, "Kuala Lumpur", state)
+    }
+
+    #[test]
+    fn accepts_postcodes_inside_the_state() {
+        assert_eq!(address("50450", "Kuala Lumpur").validate(), Ok("Kuala Lumpur"));
+        assert_eq!(address("01000", " perlis ").validate(), Ok("Perlis"));
+        // Pahang's outlying ranges
+        assert_eq!(address("39000", "Pahang").validate(), Ok("Pahang"));
+        assert_eq!(address("69000", "Pahang").validate(), Ok("Pahang"));
+        assert!(is_state("negeri sembilan"));
+    }
+
+    #[test]
+    fn rejects_postcodes_outside_the_state() {
+        let outside = |postcode: &str, state: &str| AddressError::PostcodeOutsideState { postcode: postcode.to_string(), state: state.to_string() };
+        assert_eq!(address("80100", "Kuala Lumpur").validate(), Err(outside("80100", "Kuala Lumpur")));
+        assert_eq!(address("62000", "Selangor").validate(), Err(outside("62000", "Selangor")));
+        assert_eq!(address("60001", "Kuala Lumpur").validate(), Err(outside("60001", "Kuala Lumpur")));
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for postcode in ["5045", "504500", "5O450", ""] {
+            assert_eq!(address(postcode, "Kuala Lumpur").validate(), Err(AddressError::InvalidPostcode(postcode.to_string())));
+        }
+        assert_eq!(address("50450", "Gotham").validate(), Err(AddressError::UnknownState("Gotham".to_string())));
+        assert_eq!(address("50450", " ").validate(), Err(AddressError::Missing("state")));
+        let mut abroad = address("50450", "Kuala Lumpur");
+        abroad.country = "Singapore".to_string();
+        assert_eq!(abroad.validate(), Err(AddressError::UnsupportedCountry("Singapore".to_string())));
+    }
+
+    #[test]
+    fn mistyped_postcodes_belong_to_another_state() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let mut mistyped = address("50450", "Kuala Lumpur");
+            assert!(mistyped.mistype_postcode(&mut rng, 100));
+            assert!(matches!(mistyped.validate(), Err(AddressError::PostcodeOutsideState { .. })), "{}", mistyped.postcode);
+        }
+    }
+
+    #[test]
+    fn mistypes_postcodes_at_the_configured_rate() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut mistyped = |percent: u32| (0..1000).filter(|_| address("50450", "Kuala Lumpur").mistype_postcode(&mut rng, percent)).count();
+        assert_eq!(mistyped(0), 0);
+        assert!((250..=350).contains(&mistyped(30)));
+        assert_eq!(mistyped(100), 1000);
+        // Rates above 100 percent mean every order
+        assert_eq!(mistyped(250), 1000);
+    }
+}
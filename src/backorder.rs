@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::structs::Order;
+
+pub const DEFAULT_BACKORDER_MAX_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct Backorder {
+    pub order: Order,
+    pub since: Instant,
+}
+
+// Orders waiting for stock, released oldest first per item: an order is only released
+// once no older waiting order needs any of its items
+#[derive(Debug)]
+pub struct BackorderStore {
+    queue: VecDeque<Backorder>,
+    max_wait: Duration,
+}
+
+impl Default for BackorderStore {
+    fn default() -> Self {
+        BackorderStore::new(DEFAULT_BACKORDER_MAX_WAIT)
+    }
+}
+
+impl BackorderStore {
+    pub fn new(max_wait: Duration) -> Self {
+        BackorderStore { queue: VecDeque::new(), max_wait }
+    }
+
+    pub fn push(&mut self, order: Order) {
+        self.queue.push_back(Backorder { order, since: Instant::now() });
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // Total quantity the waiting orders need of `item`
+    pub fn demand(&self, item: &str) -> i32 {
+        self.queue.iter().map(|b| b.order.quantity_of(item)).sum()
+    }
+
+    // Items with orders waiting, each once
+    pub fn items(&self) -> Vec<String> {
+        let mut items: Vec<String> = Vec::new();
+        for line in self.queue.iter().flat_map(|b| &b.order.lines) {
+            if !items.contains(&line.sku) {
+                items.push(line.sku.clone());
+            }
+        }
+        items
+    }
+
+    pub fn orders<'a>(&'a self, item: &'a str) -> impl Iterator<Item = &'a Order> {
+        self.queue.iter().map(|b| &b.order).filter(move |order| order.quantity_of(item) > 0)
+    }
+
+    // Take the oldest order that `ready` accepts and that no older waiting order
+    // shares an item with
+    pub fn pop_ready(&mut self, mut ready: impl FnMut(&Order) -> bool) -> Option<Backorder> {
+        let mut blocked: Vec<&str> = Vec::new();
+        let mut found = None;
+        for (index, backorder) in self.queue.iter().enumerate() {
+            let skus = backorder.order.lines.iter().map(|line| line.sku.as_str());
+            if !skus.clone().any(|sku| blocked.contains(&sku)) && ready(&backorder.order) {
+                found = Some(index);
+                break;
+            }
+            blocked.extend(skus);
+        }
+        self.queue.remove(found?)
+    }
+
+    // Remove every order that has waited longer than the maximum wait
+    pub fn expire(&mut self) -> Vec<Backorder> {
+        let (expired, waiting): (VecDeque<Backorder>, VecDeque<Backorder>) = self.queue.drain(..).partition(|b| b.since.elapsed() > self.max_wait);
+        self.queue = waiting;
+        expired.into()
+    }
+}
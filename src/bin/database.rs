@@ -1,38 +1,18 @@
-use std::{
-    thread,
-    sync::mpsc::{self, Sender, Receiver},
-};
+use std::process;
+use std::sync::Arc;
 
 use rts_assignment::{
-    structs::Order,
-    functions::receive_orders,
+    config,
+    services::{run_database, Summary},
+    transport::RabbitTransport,
 };
 
 fn main() {
-    let queue_name = "database_queue";
-
-    // Create channels for order processing
-    let (order_tx, order_rx): (Sender<Order>, Receiver<Order>) = mpsc::channel();
-
-    // Spawn a thread to receive orders
-    thread::spawn(move || receive_orders(queue_name, order_tx));
-
-    loop {
-        match order_rx.recv() {
-            Ok(order) => {
-                if order.id == -1 {
-                    println!("Shutting down the database system...");
-                    break;
-                }
-                println!(
-                    "[Order ID {}] Item: {}, Quantity: {}, Shipping Address: {}, Final Status: {}",
-                    order.id, order.item, order.quantity, order.shipping_address, order.final_status
-                );
-            }
-            Err(e) => {
-                println!("Error receiving order: {}", e);
-                break;
-            }
-        }
+    let config = config::init_from_env();
+    let mut summary = Summary::new(config.pricing.currency);
+    if let Err(e) = run_database(Arc::new(RabbitTransport::new(&config.broker)), &mut summary) {
+        eprintln!("Database system stopped: {}", e);
+        process::exit(1);
     }
+    print!("{}", summary);
 }
@@ -0,0 +1,76 @@
+use std::env;
+use std::process;
+
+use rts_assignment::{
+    config,
+    dead_letter::{drain, reinject, restore, DeadLetter},
+    error::Result,
+    transport::{MessageTransport, RabbitTransport},
+};
+
+const USAGE: &str = "Usage: dlq <list|requeue|purge> <queue>   (queue: payment, inventory, return_inventory, commit_inventory, delivery, monitoring, database, refund, saga, tracking)";
+
+fn print_letter(index: usize, letter: &DeadLetter) {
+    println!("#{} from {} at {} (unix time)", index, letter.source_queue, letter.timestamp);
+    println!("   Reason:  {}", letter.reason);
+    println!("   Payload: {}", letter.payload);
+}
+
+fn encode(letters: &[DeadLetter]) -> Result<Vec<String>> {
+    Ok(letters.iter().map(serde_json::to_string).collect::<serde_json::Result<_>>()?)
+}
+
+fn run(transport: &dyn MessageTransport, command: &str, dlq: &str) -> Result<()> {
+    let letters = drain(transport, dlq)?;
+    if letters.is_empty() {
+        println!("{} is empty", dlq);
+        return Ok(());
+    }
+
+    match command {
+        "list" => {
+            for (index, letter) in letters.iter().enumerate() {
+                print_letter(index, letter);
+            }
+            // Listing must not consume the letters, so put them back in the same order
+            restore(transport, dlq, encode(&letters)?)?;
+        }
+        "requeue" => {
+            for (index, letter) in letters.iter().enumerate() {
+                if let Err(e) = reinject(transport, letter) {
+                    // Keep the letters not yet re-injected on the dead-letter queue
+                    restore(transport, dlq, encode(&letters[index..])?)?;
+                    return Err(e);
+                }
+            }
+            println!("Re-injected {} messages from {}", letters.len(), dlq);
+        }
+        "purge" => println!("Discarded {} messages from {}", letters.len(), dlq),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, queue) = match args.as_slice() {
+        [command, queue] if matches!(command.as_str(), "list" | "requeue" | "purge") => (command, queue),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let config = config::init_from_env();
+    let source_queue = config.queues.resolve(queue).unwrap_or_else(|| {
+        eprintln!("Unknown queue name: {}\n{}", queue, USAGE);
+        process::exit(2);
+    });
+    let dlq = config.queues.dead_letter(&source_queue);
+
+    let transport = RabbitTransport::new(&config.broker);
+    if let Err(e) = run(&transport, command, &dlq) {
+        eprintln!("Dead-letter tool failed: {}", e);
+        process::exit(1);
+    }
+}
@@ -1,42 +1,37 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::process;
+use std::sync::Arc;
 use std::thread;
 
 use rts_assignment::{
-    structs::Order,
-    functions::{
-        process_payment,
-        receive_orders,
-        send_queue,
-    },
+    config,
+    payment,
+    services::{run_payment, run_refunds},
+    transport::{MessageTransport, RabbitTransport},
 };
 
 fn main() {
-    // Use match to determine the queue name based on the condition
-    let queue_name = "payment_queue";
+    let config = config::init_from_env();
+    let transport: Arc<dyn MessageTransport> = Arc::new(RabbitTransport::new(&config.broker));
+    let gateway = payment::from_config(&config.payment);
 
-    // Create channels for order processing
-    let (order_tx, order_rx): (Sender<Order>, Receiver<Order>) = mpsc::channel();
-
-    // Spawn a thread to receive orders
-    thread::spawn(move || receive_orders(queue_name, order_tx));
-
-    // Main thread loop for processing orders
-    loop {
-        // Process orders sequentially
-        match order_rx.recv() {
-            Ok(mut order) => {
-                if order.id == -1 {
-                    send_queue(&order, "inventory");
-                    println!("Shutting down the payment system...");
-                    break;
-                }
-
-                process_payment(&mut order);
-            }
-            Err(e) => {
-                println!("Error receiving order: {:?}", e);
-                break;
-            }
+    // Refunds keep arriving after the last payment, so they run on their own thread
+    let refunds = {
+        let (transport, gateway) = (Arc::clone(&transport), Arc::clone(&gateway));
+        thread::spawn(move || run_refunds(transport, gateway))
+    };
+    if let Err(e) = run_payment(transport, gateway) {
+        eprintln!("Payment system stopped: {}", e);
+        process::exit(1);
+    }
+    match refunds.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("Refund processing stopped: {}", e);
+            process::exit(1);
+        }
+        Err(_) => {
+            eprintln!("Refund processing crashed");
+            process::exit(1);
         }
     }
 }
@@ -0,0 +1,139 @@
+use std::env;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Local stand-in for a payment provider, answering the requests made by HttpGateway.
+// Declines and errors are drawn at random so timeouts and error codes can be exercised.
+const USAGE: &str = "Usage: payment_stub [--listen ADDR] [--decline-rate P] [--error-rate P] [--error-status CODE] [--delay-ms MS] [--seed N]";
+
+struct Options {
+    listen: String,
+    decline_rate: f64,
+    error_rate: f64,
+    error_status: u16,
+    delay: Duration,
+    seed: Option<u64>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        listen: "127.0.0.1:8099".to_string(),
+        decline_rate: 0.3,
+        error_rate: 0.1,
+        error_status: 503,
+        delay: Duration::ZERO,
+        seed: None,
+    };
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+        let invalid = || format!("Invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--listen" => options.listen = value.clone(),
+            "--decline-rate" => options.decline_rate = value.parse().map_err(|_| invalid())?,
+            "--error-rate" => options.error_rate = value.parse().map_err(|_| invalid())?,
+            "--error-status" => options.error_status = value.parse().map_err(|_| invalid())?,
+            "--delay-ms" => options.delay = Duration::from_millis(value.parse().map_err(|_| invalid())?),
+            "--seed" => options.seed = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+// Read the request headers and as much of the body as Content-Length announces
+fn read_request(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    loop {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+
+        let text = String::from_utf8_lossy(&request);
+        if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+            let length = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length:"))
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0);
+            if body.len() >= length {
+                break;
+            }
+        }
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+fn respond(stream: &mut TcpStream, status: u16, reason: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())
+}
+
+fn handle(stream: &mut TcpStream, options: &Options, rng: &mut StdRng) -> std::io::Result<()> {
+    let request = read_request(stream)?;
+    // "POST /charge HTTP/1.1"
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+    let body = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    println!("[Payment stub] {} request: {}", path, body);
+
+    thread::sleep(options.delay);
+
+    let roll: f64 = rng.gen();
+    if roll < options.error_rate {
+        println!("[Payment stub] -> {} error", options.error_status);
+        respond(stream, options.error_status, "Error", "payment provider unavailable")
+    } else if path == "/refund" {
+        // Refunds are never declined
+        println!("[Payment stub] -> refunded");
+        respond(stream, 200, "OK", "refunded")
+    } else if roll < options.error_rate + options.decline_rate {
+        println!("[Payment stub] -> declined");
+        respond(stream, 402, "Payment Required", "insufficient funds")
+    } else {
+        println!("[Payment stub] -> authorised");
+        respond(stream, 200, "OK", "authorised")
+    }
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let listener = TcpListener::bind(&options.listen).unwrap_or_else(|e| {
+        eprintln!("Cannot listen on {}: {}", options.listen, e);
+        process::exit(1);
+    });
+    println!("[Payment stub] Listening on {}", options.listen);
+
+    // Requests are answered one at a time, so a delay also holds up the next caller
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                if let Err(e) = handle(&mut stream, &options, &mut rng) {
+                    println!("[Payment stub] Request failed: {}", e);
+                }
+            }
+            Err(e) => println!("[Payment stub] Connection failed: {}", e),
+        }
+    }
+}
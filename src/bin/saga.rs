@@ -0,0 +1,16 @@
+use std::process;
+use std::sync::Arc;
+
+use rts_assignment::{
+    config,
+    services::run_saga,
+    transport::RabbitTransport,
+};
+
+fn main() {
+    let config = config::init_from_env();
+    if let Err(e) = run_saga(Arc::new(RabbitTransport::new(&config.broker))) {
+        eprintln!("Saga coordinator stopped: {}", e);
+        process::exit(1);
+    }
+}
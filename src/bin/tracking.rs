@@ -0,0 +1,72 @@
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rts_assignment::{
+    config,
+    services::{query_tracking, run_tracking},
+    tracking::TrackingLog,
+    transport::RabbitTransport,
+};
+
+const USAGE: &str = "Usage: tracking [ORDER_ID]...           (runs the tracking system, printing the history of the orders, or of every order, on shutdown)
+       tracking --query ORDER_ID...   (asks the running tracking system for the history of the orders)";
+
+// How long to wait for the running tracking system to answer a query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let query = args.first().is_some_and(|arg| arg == "--query");
+    if query {
+        args.remove(0);
+    }
+    let order_ids: Vec<i32> = args
+        .iter()
+        .map(|arg| {
+            arg.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid order id: {}\n{}", arg, USAGE);
+                process::exit(2);
+            })
+        })
+        .collect();
+    if query && order_ids.is_empty() {
+        eprintln!("No order id to query\n{}", USAGE);
+        process::exit(2);
+    }
+
+    let config = config::init_from_env();
+    let transport = RabbitTransport::new(&config.broker);
+    if query {
+        for order_id in order_ids {
+            match query_tracking(&transport, order_id, QUERY_TIMEOUT) {
+                Ok(events) => {
+                    println!("Tracking history of order {}:", order_id);
+                    for event in events {
+                        println!("  {}", event);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not query order {}: {}", order_id, e);
+                    process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    let mut log = TrackingLog::default();
+    if let Err(e) = run_tracking(Arc::new(transport), &mut log) {
+        eprintln!("Tracking system stopped: {}", e);
+        process::exit(1);
+    }
+
+    let order_ids = if order_ids.is_empty() { log.order_ids().collect() } else { order_ids };
+    for order_id in order_ids {
+        println!("Tracking history of order {}:", order_id);
+        for event in log.history(order_id) {
+            println!("  {}", event);
+        }
+    }
+}
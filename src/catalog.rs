@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use crate::config::ConfigError;
+use crate::money::Money;
+
+// Catalog compiled into the binary, used when no catalog file is found
+const BUNDLED_CATALOG: &str = include_str!("../catalog.json");
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Product {
+    pub sku: String,
+    pub name: String,
+    // Unit price, before SST
+    pub price: Money,
+    // Malaysian sales tax charged on this product, in percent
+    #[serde(default = "default_sst_percent")]
+    pub sst_percent: u32,
+    pub weight_kg: f64,
+    // Most units the warehouse holds of this product
+    pub max_capacity: i32,
+    // Stock position at which more is ordered from the supplier, a quarter of capacity by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reorder_point: Option<i32>,
+    // Stock position a purchase order brings the product back up to, capacity by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_up_to: Option<i32>,
+}
+
+fn default_sst_percent() -> u32 {
+    10
+}
+
+impl Product {
+    pub fn reorder_point(&self) -> i32 {
+        self.reorder_point.unwrap_or(self.max_capacity / 4)
+    }
+
+    pub fn order_up_to(&self) -> i32 {
+        self.order_up_to.unwrap_or(self.max_capacity)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Catalog {
+    pub products: Vec<Product>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        serde_json::from_str(BUNDLED_CATALOG).expect("bundled catalog.json is invalid")
+    }
+}
+
+impl Catalog {
+    pub fn from_file(path: &str) -> Result<Catalog, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+        let catalog: Catalog = serde_json::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_string(), e))?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    // Read the catalog file when it exists, otherwise fall back to the bundled catalog
+    pub fn load(path: &str) -> Result<Catalog, ConfigError> {
+        if Path::new(path).exists() {
+            Catalog::from_file(path)
+        } else {
+            println!("Catalog file {} not found, using the bundled catalog", path);
+            Ok(Catalog::default())
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.products.is_empty() {
+            return Err(ConfigError::InvalidValue("catalog", "no products".to_string()));
+        }
+        for (index, product) in self.products.iter().enumerate() {
+            if self.products[..index].iter().any(|other| other.sku == product.sku) {
+                return Err(ConfigError::InvalidValue("catalog", format!("duplicate SKU {}", product.sku)));
+            }
+            if product.max_capacity <= 0 || product.price.minor < 0 || product.weight_kg < 0.0 {
+                return Err(ConfigError::InvalidValue("catalog", format!("invalid values for SKU {}", product.sku)));
+            }
+            if product.reorder_point() < 0 || product.reorder_point() >= product.order_up_to() || product.order_up_to() > product.max_capacity {
+                return Err(ConfigError::InvalidValue("catalog", format!("invalid reorder levels for SKU {}", product.sku)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find(&self, sku: &str) -> Option<&Product> {
+        self.products.iter().find(|product| product.sku == sku)
+    }
+}
+
+// Install the process-wide catalog, normally once at startup
+pub fn init(catalog: Catalog) {
+    if CATALOG.set(catalog).is_err() {
+        println!("Catalog already initialised, keeping the existing one");
+    }
+}
+
+// The process-wide catalog, falling back to the bundled one when init was never called
+pub fn get() -> &'static Catalog {
+    CATALOG.get_or_init(Catalog::default)
+}
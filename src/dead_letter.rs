@@ -0,0 +1,82 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::config;
+use crate::error::Result;
+use crate::transport::MessageTransport;
+
+// A message that could not be processed, parked on the dead-letter queue of the
+// broker queue it was taken from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetter {
+    // The original message exactly as it was received
+    pub payload: String,
+    pub reason: String,
+    pub source_queue: String,
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl DeadLetter {
+    pub fn new(payload: &str, reason: &str, source_queue: &str) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        DeadLetter {
+            payload: payload.to_string(),
+            reason: reason.to_string(),
+            source_queue: source_queue.to_string(),
+            timestamp,
+        }
+    }
+}
+
+// Park a message on the dead-letter queue of `source_queue` (a broker queue name)
+pub fn dead_letter(transport: &dyn MessageTransport, source_queue: &str, payload: &str, reason: &str) -> Result<()> {
+    let letter = DeadLetter::new(payload, reason, source_queue);
+    let dlq = config::get().queues.dead_letter(source_queue);
+    println!("[{}] Dead-lettering message to {}: {}", source_queue, dlq, reason);
+    transport.send(serde_json::to_string(&letter)?, &dlq)
+}
+
+// Take every message currently on a dead-letter queue without blocking. Taking a
+// message removes it, so all of them are read before any is parsed; a message that is
+// not a dead letter is put straight back, and if reading fails part way the messages
+// already taken are returned to the queue before the error is passed on.
+pub fn drain(transport: &dyn MessageTransport, dlq: &str) -> Result<Vec<DeadLetter>> {
+    let mut messages = Vec::new();
+    loop {
+        match transport.try_recv(dlq) {
+            Ok(Some(message)) => messages.push(message),
+            Ok(None) => break,
+            Err(e) => {
+                restore(transport, dlq, messages)?;
+                return Err(e);
+            }
+        }
+    }
+
+    let mut letters = Vec::new();
+    let mut unreadable = Vec::new();
+    for message in messages {
+        match serde_json::from_str(&message) {
+            Ok(letter) => letters.push(letter),
+            Err(e) => {
+                println!("[{}] Keeping a message that is not a dead letter: {}", dlq, e);
+                unreadable.push(message);
+            }
+        }
+    }
+    restore(transport, dlq, unreadable)?;
+    Ok(letters)
+}
+
+// Put messages taken off a dead-letter queue back on it, in the same order
+pub fn restore(transport: &dyn MessageTransport, dlq: &str, messages: Vec<String>) -> Result<()> {
+    for message in messages {
+        transport.send(message, dlq)?;
+    }
+    Ok(())
+}
+
+// Put a dead letter's original payload back on its source queue
+pub fn reinject(transport: &dyn MessageTransport, letter: &DeadLetter) -> Result<()> {
+    transport.send(letter.payload.clone(), &letter.source_queue)
+}
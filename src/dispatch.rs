@@ -0,0 +1,258 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::routing::TransportMode;
+use crate::shipping::{format_time, Zone, DAY_MS, HOUR_MS, MYT_OFFSET_MS};
+
+// Days ahead searched for a run with room before a booking is refused
+const BOOKING_HORIZON_DAYS: u64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CourierConfig {
+    pub name: String,
+    pub mode: TransportMode,
+    // Shipments one dispatch run carries
+    pub capacity: u32,
+    // Hours of the day, in Malaysia time, the courier's runs leave
+    pub departures: Vec<u32>,
+    // Couriers tried, in order, when this courier's run is full
+    #[serde(default)]
+    pub alternates: Vec<String>,
+}
+
+// Two land couriers, the smaller one taking the lorry's overflow, a daily ferry and
+// three flights a day
+pub fn default_couriers() -> Vec<CourierConfig> {
+    let courier = |name: &str, mode, capacity, departures: &[u32], alternates: &[&str]| CourierConfig {
+        name: name.to_string(),
+        mode,
+        capacity,
+        departures: departures.to_vec(),
+        alternates: alternates.iter().map(|name| name.to_string()).collect(),
+    };
+    vec![
+        courier("Lorry", TransportMode::Land, 4, &[10, 16], &["Van"]),
+        courier("Van", TransportMode::Land, 2, &[10, 16], &[]),
+        courier("Ferry", TransportMode::Sea, 6, &[18], &[]),
+        courier("Cargo Flight", TransportMode::Air, 3, &[8, 14, 20], &[]),
+    ]
+}
+
+// Where a shipment was booked
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Booking {
+    pub courier: String,
+    pub mode: TransportMode,
+    // When the run leaves, in milliseconds since the Unix epoch
+    pub departure: u64,
+}
+
+impl fmt::Display for Booking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) leaving {}", self.courier, self.mode, format_time(self.departure))
+    }
+}
+
+// Orders of one run going to the same zone
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub zone: Option<Zone>,
+    pub orders: Vec<i32>,
+}
+
+// One departure of a courier and the batches loaded on it
+#[derive(Debug, Clone)]
+pub struct DispatchRun {
+    pub courier: String,
+    pub departure: u64,
+    pub capacity: u32,
+    pub batches: Vec<Batch>,
+}
+
+impl DispatchRun {
+    pub fn shipments(&self) -> u32 {
+        self.batches.iter().map(|batch| batch.orders.len() as u32).sum()
+    }
+
+    pub fn utilisation_percent(&self) -> u32 {
+        // Couriers built without the config checks may have no capacity
+        self.shipments() * 100 / self.capacity.max(1)
+    }
+}
+
+impl fmt::Display for DispatchRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let batches: Vec<String> = self
+            .batches
+            .iter()
+            .map(|batch| match batch.zone {
+                Some(zone) => format!("{} {}", zone, batch.orders.len()),
+                None => format!("unknown zone {}", batch.orders.len()),
+            })
+            .collect();
+        write!(
+            f,
+            "{} {}: {}/{} shipments ({}%), {}",
+            self.courier,
+            format_time(self.departure),
+            self.shipments(),
+            self.capacity,
+            self.utilisation_percent(),
+            batches.join(", ")
+        )
+    }
+}
+
+// Runs booked so far, filled in the order shipments are booked
+#[derive(Debug)]
+pub struct DispatchBoard {
+    couriers: Vec<CourierConfig>,
+    runs: Vec<DispatchRun>,
+}
+
+impl DispatchBoard {
+    pub fn new(couriers: &[CourierConfig]) -> Self {
+        let mut couriers = couriers.to_vec();
+        for courier in &mut couriers {
+            courier.departures.sort_unstable();
+        }
+        DispatchBoard { couriers, runs: Vec::new() }
+    }
+
+    pub fn courier(&self, name: &str) -> Option<&CourierConfig> {
+        self.couriers.iter().find(|courier| courier.name == name)
+    }
+
+    // The courier shipments routed to `mode` are booked on first
+    pub fn courier_for(&self, mode: TransportMode) -> Option<&CourierConfig> {
+        self.couriers.iter().find(|courier| courier.mode == mode)
+    }
+
+    // Book a shipment ready to leave at `ready_at` on the earliest run with room, of the
+    // courier for `mode` or any of its alternates. When runs leave at the same time, the
+    // courier for `mode` comes first, then the alternates in the order listed.
+    pub fn book(&mut self, order_id: i32, mode: TransportMode, zone: Option<Zone>, ready_at: u64) -> Option<Booking> {
+        let (courier, mode, capacity, departure) = self.find_slot(mode, ready_at)?;
+        let run = match self.runs.iter().position(|run| run.courier == courier && run.departure == departure) {
+            Some(index) => &mut self.runs[index],
+            None => {
+                self.runs.push(DispatchRun { courier: courier.clone(), departure, capacity, batches: Vec::new() });
+                self.runs.last_mut().expect("run was just added")
+            }
+        };
+        match run.batches.iter_mut().find(|batch| batch.zone == zone) {
+            Some(batch) => batch.orders.push(order_id),
+            None => run.batches.push(Batch { zone, orders: vec![order_id] }),
+        }
+        Some(Booking { courier, mode, departure })
+    }
+
+    // Courier, mode, capacity and departure of the earliest run with room, or None when
+    // every run up to the booking horizon is full
+    fn find_slot(&self, mode: TransportMode, ready_at: u64) -> Option<(String, TransportMode, u32, u64)> {
+        let primary = self.courier_for(mode)?;
+        let mut candidates = vec![primary];
+        candidates.extend(primary.alternates.iter().filter_map(|name| self.courier(name)));
+
+        let mut slots: Vec<(u64, usize)> = candidates
+            .iter()
+            .enumerate()
+            .flat_map(|(rank, courier)| departures(courier, ready_at).map(move |departure| (departure, rank)))
+            .collect();
+        slots.sort_unstable();
+        slots.into_iter().find_map(|(departure, rank)| {
+            let courier = candidates[rank];
+            (self.shipments_on(&courier.name, departure) < courier.capacity)
+                .then(|| (courier.name.clone(), courier.mode, courier.capacity, departure))
+        })
+    }
+
+    fn shipments_on(&self, courier: &str, departure: u64) -> u32 {
+        self.runs
+            .iter()
+            .find(|run| run.courier == courier && run.departure == departure)
+            .map_or(0, |run| run.shipments())
+    }
+
+    // Every run booked, earliest departure first
+    pub fn runs(&self) -> Vec<&DispatchRun> {
+        let mut runs: Vec<&DispatchRun> = self.runs.iter().collect();
+        runs.sort_by(|a, b| (a.departure, &a.courier).cmp(&(b.departure, &b.courier)));
+        runs
+    }
+}
+
+// The courier's departures at or after `from`, up to the booking horizon
+fn departures(courier: &CourierConfig, from: u64) -> impl Iterator<Item = u64> + '_ {
+    // Midnight in Malaysia of the day `from` falls on
+    let midnight = (from + MYT_OFFSET_MS) / DAY_MS * DAY_MS - MYT_OFFSET_MS;
+    (0..BOOKING_HORIZON_DAYS)
+        .flat_map(move |day| courier.departures.iter().map(move |&hour| midnight + day * DAY_MS + hour as u64 * HOUR_MS))
+        .filter(move |&departure| departure >= from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Midnight in Malaysia on 2026-10-19
+    const MIDNIGHT: u64 = 1_792_368_000_000 - MYT_OFFSET_MS;
+
+    fn courier(name: &str, capacity: u32, departures: &[u32], alternates: &[&str]) -> CourierConfig {
+        CourierConfig {
+            name: name.to_string(),
+            mode: TransportMode::Land,
+            capacity,
+            departures: departures.to_vec(),
+            alternates: alternates.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn books_the_earliest_run_with_room_across_alternates() {
+        let mut board = DispatchBoard::new(&[courier("Lorry", 1, &[16, 8], &["Van"]), courier("Van", 1, &[9], &[])]);
+        let book = |board: &mut DispatchBoard, id| board.book(id, TransportMode::Land, Some(Zone::Peninsular), MIDNIGHT).unwrap();
+
+        let at = |hour: u64| MIDNIGHT + hour * HOUR_MS;
+        assert_eq!(book(&mut board, 1), Booking { courier: "Lorry".to_string(), mode: TransportMode::Land, departure: at(8) });
+        assert_eq!(book(&mut board, 2).departure, at(9));
+        assert_eq!(book(&mut board, 3).departure, at(16));
+        // The next day's first run, not a day later in lockstep with the van
+        let next = book(&mut board, 4);
+        assert_eq!((next.courier.as_str(), next.departure), ("Lorry", at(24 + 8)));
+    }
+
+    #[test]
+    fn refuses_only_once_every_run_up_to_the_horizon_is_full() {
+        let mut board = DispatchBoard::new(&[courier("Lorry", 1, &[8, 12, 16], &["Van"]), courier("Van", 1, &[9], &[])]);
+        let slots = 4 * BOOKING_HORIZON_DAYS as i32;
+        for id in 0..slots {
+            assert!(board.book(id, TransportMode::Land, None, MIDNIGHT).is_some(), "booking {} refused", id);
+        }
+        assert_eq!(board.book(slots, TransportMode::Land, None, MIDNIGHT), None);
+        assert!(board.runs().iter().all(|run| run.utilisation_percent() == 100));
+    }
+
+    #[test]
+    fn batches_a_run_by_zone() {
+        let mut board = DispatchBoard::new(&[courier("Lorry", 3, &[10], &[])]);
+        for (id, zone) in [(1, Zone::Peninsular), (2, Zone::EastMalaysia), (3, Zone::Peninsular)] {
+            board.book(id, TransportMode::Land, Some(zone), MIDNIGHT).unwrap();
+        }
+        let runs = board.runs();
+        assert_eq!(runs.len(), 1);
+        let orders: Vec<&[i32]> = runs[0].batches.iter().map(|batch| batch.orders.as_slice()).collect();
+        assert_eq!(orders, [&[1, 3][..], &[2][..]]);
+    }
+
+    #[test]
+    fn utilisation_of_a_run_without_capacity() {
+        let run = DispatchRun { courier: "Van".to_string(), departure: MIDNIGHT, capacity: 0, batches: Vec::new() };
+        assert_eq!(run.utilisation_percent(), 0);
+    }
+
+    #[test]
+    fn no_courier_for_the_mode() {
+        let mut board = DispatchBoard::new(&[courier("Lorry", 1, &[10], &[])]);
+        assert_eq!(board.book(1, TransportMode::Air, None, MIDNIGHT), None);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use crate::address::AddressError;
+use crate::config::ConfigError;
+use crate::journal::JournalError;
+use crate::money::MoneyError;
+use crate::routing::RoutingError;
+use crate::structs::InvalidTransition;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    // The broker or in-process queue could not be reached
+    Transport(String),
+    // A message could not be encoded or decoded
+    Serialization(serde_json::Error),
+    // The inventory could not satisfy or record an operation
+    Inventory(String),
+    // The payment step failed for a reason other than a declined payment
+    Payment(String),
+    StateTransition(InvalidTransition),
+    UnknownQueue(String),
+    // The in-process channel to the service loop was closed
+    Disconnected(String),
+    Config(ConfigError),
+    // The inventory journal could not be read or written
+    Journal(JournalError),
+    // The courier routing rules could not be read or parsed
+    Routing(RoutingError),
+    // No courier run had room for a shipment
+    Dispatch(String),
+    // The order's shipping address cannot be shipped to
+    Address(AddressError),
+    // Amounts of one order or message are in different currencies or out of range
+    Money(MoneyError),
+}
+
+impl Error {
+    // Errors worth retrying because the same operation may succeed later
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Transport(_))
+    }
+
+    // Errors about one message that leave the service able to carry on
+    pub fn is_per_message(&self) -> bool {
+        matches!(
+            self,
+            Error::Serialization(_) | Error::Inventory(_) | Error::Payment(_) | Error::StateTransition(_) | Error::Dispatch(_)
+                | Error::Address(_)
+                | Error::Money(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::Serialization(e) => write!(f, "serialization error: {}", e),
+            Error::Inventory(e) => write!(f, "inventory error: {}", e),
+            Error::Payment(e) => write!(f, "payment error: {}", e),
+            Error::StateTransition(e) => write!(f, "{}", e),
+            Error::UnknownQueue(name) => write!(f, "unknown queue name: {}", name),
+            Error::Disconnected(what) => write!(f, "{} channel disconnected", what),
+            Error::Config(e) => write!(f, "configuration error: {}", e),
+            Error::Journal(e) => write!(f, "{}", e),
+            Error::Routing(e) => write!(f, "{}", e),
+            Error::Dispatch(e) => write!(f, "dispatch error: {}", e),
+            Error::Address(e) => write!(f, "invalid shipping address: {}", e),
+            Error::Money(e) => write!(f, "invalid amount: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Serialization(e) => Some(e),
+            Error::StateTransition(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Journal(e) => Some(e),
+            Error::Routing(e) => Some(e),
+            Error::Address(e) => Some(e),
+            Error::Money(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<amiquip::Error> for Error {
+    fn from(e: amiquip::Error) -> Self {
+        Error::Transport(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialization(e)
+    }
+}
+
+impl From<InvalidTransition> for Error {
+    fn from(e: InvalidTransition) -> Self {
+        Error::StateTransition(e)
+    }
+}
+
+impl From<JournalError> for Error {
+    fn from(e: JournalError) -> Self {
+        Error::Journal(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<AddressError> for Error {
+    fn from(e: AddressError) -> Self {
+        Error::Address(e)
+    }
+}
+
+impl From<MoneyError> for Error {
+    fn from(e: MoneyError) -> Self {
+        Error::Money(e)
+    }
+}
+
+impl From<RoutingError> for Error {
+    fn from(e: RoutingError) -> Self {
+        Error::Routing(e)
+    }
+}
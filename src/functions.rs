@@ -1,230 +1,692 @@
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
 use std::time::Duration;
+use serde::Serialize;
 use rand::{
     prelude::SliceRandom,
     Rng,
 };
-use crate::rabbitmq::{recv_msg, send_msg};
-use crate::structs::{Inventory, Order};
+use crate::address::Address;
+use crate::backorder::BackorderStore;
+use crate::catalog;
+use crate::config::{self, InventoryConfig};
+use crate::dead_letter::dead_letter;
+use crate::dispatch::{Booking, DispatchBoard};
+use crate::error::{Error, Result};
+use crate::payment::{PaymentGateway, PaymentOutcome};
+use crate::replenishment::StockoutPolicy;
+use crate::routing::{Parcel, RouteTable, TransportMode};
+use crate::saga::{report, SagaEventKind, SagaStep};
+use crate::shipping::{format_date, format_time, ServiceLevel, Shipment, Zone, HOUR_MS};
+use crate::structs::{unix_millis, Control, Envelope, Order, OrderLine, OrderStatus, RefundStatus};
+use crate::tracking::TrackingStage;
+use crate::transport::MessageTransport;
+use crate::warehouse::{FulfilmentStrategy, Warehouses};
+
+// Attempts made to publish a message while the transport reports transient errors
+const SEND_ATTEMPTS: u32 = 3;
+const SEND_RETRY_DELAY: Duration = Duration::from_millis(200);
 
 // Common functions
-pub fn send_queue(order: &Order, queue_name: &str) {
-    let serialized_order = serde_json::to_string(&order).unwrap();
+pub fn send_queue(transport: &dyn MessageTransport, order: &Order, queue_name: &str) -> Result<()> {
+    send_envelope(transport, &Envelope::Message(order), queue_name)
+}
+
+pub fn send_control(transport: &dyn MessageTransport, control: Control, queue_name: &str) -> Result<()> {
+    send_envelope(transport, &Envelope::<&Order>::Control(control), queue_name)
+}
 
-    match queue_name {
-        "payment" => send_msg(serialized_order, "payment_queue").unwrap(),
-        "inventory" => send_msg(serialized_order, "inventory_queue").unwrap(),
-        "monitoring" => send_msg(serialized_order, "monitor_queue").unwrap(),
-        "database" => send_msg(serialized_order, "database_queue").unwrap(),
-        "delivery" => send_msg(serialized_order, "delivery_queue").unwrap(),
-        "return_inventory" => send_msg(serialized_order, "return_inventory_queue").unwrap(),
-        _ => {
-            println!("Unknown queue name: {}", queue_name);
-            return;
-        },
+pub(crate) fn send_envelope<T: Serialize>(transport: &dyn MessageTransport, envelope: &Envelope<T>, queue_name: &str) -> Result<()> {
+    let serialized = serde_json::to_string(envelope)?;
+    let queue = config::get()
+        .queues
+        .resolve(queue_name)
+        .ok_or_else(|| Error::UnknownQueue(queue_name.to_string()))?;
+
+    let mut attempt = 1;
+    loop {
+        match transport.send(serialized.clone(), &queue) {
+            Err(e) if e.is_transient() && attempt < SEND_ATTEMPTS => {
+                println!("Sending to {} failed ({}), retrying {}/{}...", queue, e, attempt, SEND_ATTEMPTS - 1);
+                thread::sleep(SEND_RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+            result => return result,
+        }
     }
 }
 
-pub fn receive_orders(queue_name: &str, sender: Sender<Order>) {
+// An envelope taken off a queue together with the text it arrived as, so an order
+// that cannot be processed is dead-lettered exactly as it was received
+pub type Received = (Envelope<Order>, String);
+
+// A message passed on to the service with the transport's tag to acknowledge it by,
+// if it is still unacknowledged
+pub type Tagged = (Received, Option<u64>);
+
+// Forward orders from the queue to the service, handling Pause/Resume/Ping here.
+// Shutdown is only passed on once all `producers` upstream services have sent one.
+// Messages passed on are acknowledged by the service once processed; the rest are
+// acknowledged here once dealt with.
+pub fn receive_orders(
+    transport: Arc<dyn MessageTransport>,
+    queue_name: &str,
+    producers: usize,
+    sender: Sender<Tagged>,
+) -> Result<()> {
+    let mut shutdowns = 0;
+    let mut paused = false;
+    let mut held: Vec<(Order, String)> = Vec::new();
+
+    let forward = |envelope: Envelope<Order>, raw: String, tag: Option<u64>, held: &mut Vec<(Order, String)>, shutdowns: usize| {
+        hand_over(transport.as_ref(), queue_name, &sender, ((envelope, raw), tag), held, shutdowns)
+    };
+
     loop {
-        let order = recv_msg(queue_name);
-        if !order.is_empty() {
-            if let Ok(deserialized_order) = serde_json::from_str::<Order>(&order) {
-                if deserialized_order.id == -1 {
-                    sender.send(deserialized_order).unwrap();
-                    break;
+        let (tag, message) = transport.recv_unacked(queue_name)?;
+        if message.is_empty() {
+            transport.ack(queue_name, tag)?;
+            continue;
+        }
+        match serde_json::from_str::<Envelope<Order>>(&message) {
+            Ok(Envelope::Message(order)) => {
+                if paused {
+                    // Held orders are acknowledged, or they would fill the prefetch window
+                    // and keep the Resume from arriving. If the service stops, they are
+                    // put back on the queue.
+                    held.push((order, message));
+                    transport.ack(queue_name, tag)?;
                 } else {
-                    sender.send(deserialized_order).unwrap();
+                    forward(Envelope::Message(order), message, Some(tag), &mut held, shutdowns)?;
+                }
+            }
+            Ok(Envelope::Control(Control::Shutdown)) => {
+                shutdowns += 1;
+                println!("[{}] Shutdown received from {}/{} producers", queue_name, shutdowns, producers);
+                if shutdowns >= producers {
+                    release(&mut held, |order, raw, held| forward(Envelope::Message(order), raw, None, held, shutdowns))?;
+                    return forward(Envelope::Control(Control::Shutdown), message, Some(tag), &mut held, shutdowns - 1);
                 }
+                transport.ack(queue_name, tag)?;
+            }
+            Ok(Envelope::Control(Control::Drain)) => {
+                println!("[{}] Draining...", queue_name);
+                release(&mut held, |order, raw, held| forward(Envelope::Message(order), raw, None, held, shutdowns))?;
+                return forward(Envelope::Control(Control::Drain), message, Some(tag), &mut held, shutdowns);
+            }
+            Ok(Envelope::Control(Control::Pause)) => {
+                println!("[{}] Paused", queue_name);
+                paused = true;
+                transport.ack(queue_name, tag)?;
+            }
+            Ok(Envelope::Control(Control::Resume)) => {
+                println!("[{}] Resumed, releasing {} held orders", queue_name, held.len());
+                paused = false;
+                transport.ack(queue_name, tag)?;
+                release(&mut held, |order, raw, held| forward(Envelope::Message(order), raw, None, held, shutdowns))?;
+            }
+            Ok(Envelope::Control(Control::Ping)) => {
+                println!("[{}] Ping received, {} orders held", queue_name, held.len());
+                transport.ack(queue_name, tag)?;
+            }
+            Ok(Envelope::Control(Control::Recall(order_id))) => {
+                // Passed on straight away, even while paused, so the service drops the order
+                forward(Envelope::Control(Control::Recall(order_id)), message, Some(tag), &mut held, shutdowns)?;
+            }
+            Err(e) => {
+                // Park messages we cannot decode instead of silently losing them
+                dead_letter(transport.as_ref(), queue_name, &message, &Error::from(e).to_string())?;
+                transport.ack(queue_name, tag)?;
+            }
+        }
+    }
+}
+
+// Forward the held orders, oldest first
+fn release<F>(held: &mut Vec<(Order, String)>, mut forward: F) -> Result<()>
+where
+    F: FnMut(Order, String, &mut Vec<(Order, String)>) -> Result<()>,
+{
+    while !held.is_empty() {
+        let (order, raw) = held.remove(0);
+        forward(order, raw, held)?;
+    }
+    Ok(())
+}
+
+// Pass a message on to the service. If the service has stopped, this receiver is
+// finished (a restarted service reads the queue through a new one), so everything
+// taken off the queue and not passed on goes back on it: the message, the orders
+// still held and the `shutdowns` counted besides the message.
+fn hand_over(
+    transport: &dyn MessageTransport,
+    queue_name: &str,
+    sender: &Sender<Tagged>,
+    tagged: Tagged,
+    held: &mut Vec<(Order, String)>,
+    shutdowns: usize,
+) -> Result<()> {
+    let Err(unsent) = sender.send(tagged) else {
+        return Ok(());
+    };
+    let ((_, raw), tag) = unsent.0;
+    let shutdown = serde_json::to_string(&Envelope::<Order>::Control(Control::Shutdown))?;
+    let messages = std::iter::once(raw)
+        .chain(held.drain(..).map(|(_, raw)| raw))
+        .chain(std::iter::repeat_n(shutdown, shutdowns));
+    for message in messages {
+        transport.send(message, queue_name)?;
+    }
+    if let Some(tag) = tag {
+        transport.ack(queue_name, tag)?;
+    }
+    println!("[{}] Service has stopped, returned unprocessed messages to the queue", queue_name);
+    Err(Error::Disconnected(queue_name.to_string()))
+}
+
+// The service end of `receive_orders`. Each message is acknowledged once the service
+// comes back for the next one, after its handler has returned, so the broker's
+// prefetch window only covers messages the service has yet to finish.
+pub struct Inbox {
+    transport: Arc<dyn MessageTransport>,
+    queue_name: String,
+    receiver: Receiver<Tagged>,
+    unacked: Cell<Option<u64>>,
+}
+
+impl Inbox {
+    pub fn new(transport: Arc<dyn MessageTransport>, queue_name: &str, receiver: Receiver<Tagged>) -> Self {
+        Inbox { transport, queue_name: queue_name.to_string(), receiver, unacked: Cell::new(None) }
+    }
+
+    pub fn recv(&self) -> std::result::Result<Received, RecvError> {
+        self.ack();
+        let (received, tag) = self.receiver.recv()?;
+        self.unacked.set(tag);
+        Ok(received)
+    }
+
+    pub fn try_recv(&self) -> std::result::Result<Received, TryRecvError> {
+        self.ack();
+        let (received, tag) = self.receiver.try_recv()?;
+        self.unacked.set(tag);
+        Ok(received)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> std::result::Result<Received, RecvTimeoutError> {
+        self.ack();
+        let (received, tag) = self.receiver.recv_timeout(timeout)?;
+        self.unacked.set(tag);
+        Ok(received)
+    }
+
+    fn ack(&self) {
+        if let Some(tag) = self.unacked.take() {
+            if let Err(e) = self.transport.ack(&self.queue_name, tag) {
+                // The broker redelivers the message once the connection is back
+                println!("[{}] Could not acknowledge a processed message: {}", self.queue_name, e);
             }
         }
     }
 }
 
+// The last message is acknowledged when the service stops, even on an error: the
+// error has been reported, and a restarted service given the same message again
+// would most likely stop on it again.
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        self.ack();
+    }
+}
+
+fn lock_inventory(inventory: &Arc<Mutex<Warehouses>>) -> Result<MutexGuard<'_, Warehouses>> {
+    inventory.lock().map_err(|_| Error::Inventory("inventory lock poisoned".to_string()))
+}
+
 // Order system functions
-pub fn generate_orders(order_tx: &Sender<Order>, limit: i32) {
+pub fn generate_orders(order_tx: &Sender<Order>, order_ids: RangeInclusive<i32>, interval: Duration) {
     let mut rng = rand::thread_rng();
-    let item_list = vec![
-        "T-Shirt","Hoodie", "Skirt", "Dress", "Wallet", "Shoes",
-        "Socks", "Pants", "Shorts"
-    ];
+    let item_list = &catalog::get().products;
+    let mistyped_percent = config::get().orders.mistyped_postcode_percent;
 
+    // City, postcode and state
     let location_list = vec![
-        "Johor", "Kedah", "Kelantan", "Kuala Lumpur", "Labuan", "Melaka",
-        "Negeri Sembilan", "Pahang", "Penang", "Perak", "Perlis",
-        "Putrajaya", "Sabah", "Sarawak", "Selangor", "Terengganu"
+        ("Johor Bahru", "80100", "Johor"), ("Alor Setar", "05000", "Kedah"),
+        ("Kota Bharu", "15000", "Kelantan"), ("Kuala Lumpur", "50450", "Kuala Lumpur"),
+        ("Victoria", "87000", "Labuan"), ("Melaka", "75000", "Melaka"),
+        ("Seremban", "70000", "Negeri Sembilan"), ("Kuantan", "25000", "Pahang"),
+        ("George Town", "10200", "Penang"), ("Ipoh", "30000", "Perak"),
+        ("Kangar", "01000", "Perlis"), ("Putrajaya", "62000", "Putrajaya"),
+        ("Kota Kinabalu", "88000", "Sabah"), ("Kuching", "93000", "Sarawak"),
+        ("Shah Alam", "40000", "Selangor"), ("Kuala Terengganu", "20000", "Terengganu"),
     ];
+    let recipient_list = ["Nur Aisyah", "Tan Wei Ming", "Muthu Kumar", "Siti Hajar", "Jason Lim", "Dayang Nurul"];
+    let street_list = ["12 Jalan Merdeka", "8 Lorong Bunga Raya", "21 Jalan Sultan", "5 Persiaran Damai"];
 
-    for order_id in 1..=limit {
-        let random_quantity: i32 = rng.gen_range(1..=10);
-        let random_item = item_list.choose(&mut rng).unwrap().to_string();
-        let random_location = location_list.choose(&mut rng).unwrap().to_string();
-        let payment_status = false;
-        let delivery_status = false;
-        let final_status = "Pending".to_string();
-
-        let order = Order {
-            id: order_id,
-            item: random_item,
-            quantity: random_quantity,
-            shipping_address: random_location,
-            payment_status,
-            delivery_status,
-            final_status,
-        };
-
-        order_tx.send(order).unwrap();
-        thread::sleep(Duration::from_secs(1));
-    }
+    for order_id in order_ids {
+        // One to three different products per order
+        let line_count = rng.gen_range(1..=3);
+        let lines = item_list
+            .choose_multiple(&mut rng, line_count)
+            .map(|product| OrderLine::new(&product.sku, rng.gen_range(1..=5)))
+            .collect();
+        let (city, postcode, state) = *location_list.choose(&mut rng).unwrap();
+        let recipient = recipient_list.choose(&mut rng).unwrap();
+        let street = street_list.choose(&mut rng).unwrap();
+        let mut address = Address::new(recipient, &[street], postcode, city, state);
+        // When configured, some orders are mistyped with a postcode from another state
+        // and are rejected when they are taken
+        address.mistype_postcode(&mut rng, mistyped_percent);
 
-    // Send a sentinel order to indicate the end of orders
-    let end_order = Order {
-        id: -1, // Use a specific id or other field to indicate the end
-        item: String::new(),
-        quantity: 0,
-        shipping_address: String::new(),
-        payment_status: false,
-        delivery_status: false,
-        final_status: String::new(),
-    };
+        let mut order = Order::new(order_id, lines, address);
+        // One order in five pays for express shipping
+        if rng.gen_bool(0.2) {
+            order.service_level = ServiceLevel::Express;
+        }
 
-    order_tx.send(end_order).unwrap();
+        // Stop generating once nobody is listening any more
+        if order_tx.send(order).is_err() {
+            break;
+        }
+        thread::sleep(interval);
+    }
 }
 
 // Payment system functions
-pub fn process_payment(order: &mut Order) {
-    println!("[Order ID {}] Payment system received order", order.id);
-    let mut rng = rand::thread_rng();
-    // Simulate a payment process with a 50% success rate
-    order.payment_status = rng.gen_bool(0.5);
+pub fn process_payment(transport: &dyn MessageTransport, gateway: &dyn PaymentGateway, order: &mut Order) -> Result<()> {
+    println!("[Order ID {}] Payment system received order, charging {} for {} line(s)", order.id, order.totals.total, order.lines.len());
+    let outcome = gateway.charge(order);
+    let next = if outcome == PaymentOutcome::Authorised { OrderStatus::Paid } else { OrderStatus::PaymentFailed };
+    order.transition(next)?;
 
     // Route the order based on the payment status
-    if order.payment_status {
+    if order.status == OrderStatus::Paid {
         println!("[Order ID {}] Payment successful", order.id);
+        report(transport, order, SagaEventKind::StepCompleted(SagaStep::ChargePayment))?;
         println!("[Order ID {}] Send to inventory system for processing...", order.id);
-        send_queue(order, "inventory");
+        send_queue(transport, order, "inventory")?;
     } else {
-        println!("[Order ID {}] Payment failed", order.id);
+        println!("[Order ID {}] Payment failed: {}", order.id, outcome);
         println!("[Order ID {}] Send to monitoring system...", order.id);
-        send_queue(order, "monitoring");
+        send_queue(transport, order, "monitoring")?;
     }
 
     println!("--------------------------------------------------");
+    Ok(())
 }
 
 // Inventory system functions
-pub fn handle_return(inventory: &Arc<Mutex<Inventory>>, order: &Order) {
-    let mut inv = inventory.lock().unwrap();
-    println!("[Return ID {}] Handling return Item: {}, Quantity: {}", order.id, order.item, order.quantity);
-    inv.add_stock(&order.item, order.quantity);
-    println!("[Return ID {}] Return item successfully. New stock {}: {}", order.id, order.item, inv.get_stock(&order.item));
+// Orders on the return queue were never delivered, so every line's reservation is released
+pub fn handle_return(inventory: &Arc<Mutex<Warehouses>>, order: &Order) -> Result<()> {
+    let mut inv = lock_inventory(inventory)?;
+    println!("[Return ID {}] Handling return Items: {}", order.id, order.describe_lines());
+    let released = inv.release(order.id)?;
+    if released.is_empty() {
+        println!("[Return ID {}] No reservation held, it has already expired or been committed", order.id);
+    }
+    for (warehouse, reservation) in released {
+        for line in reservation.lines {
+            let available = inv.get(&warehouse).map_or(0, |w| w.inventory.available(&line.sku));
+            println!("[Return ID {}] Released {} at {}. Available there: {}", order.id, line, warehouse, available);
+        }
+    }
+    Ok(())
 }
 
-pub fn inventory_checking(inventory: &Arc<Mutex<Inventory>>, order: &mut Order) {
-    let mut inv = inventory.lock().unwrap();
+// Orders on the commit queue have been delivered
+pub fn commit_stock(inventory: &Arc<Mutex<Warehouses>>, order: &Order) -> Result<()> {
+    let mut inv = lock_inventory(inventory)?;
+    let committed = inv.commit(order.id)?;
+    for (warehouse, reservation) in &committed {
+        for line in &reservation.lines {
+            println!("[Order ID {}] Committed {} at {}", order.id, line, warehouse);
+        }
+    }
+    if committed.is_empty() {
+        // The reservations expired while the order was out for delivery; the goods
+        // have still left the warehouses
+        println!("[Order ID {}] No reservation held, deducting delivered stock directly", order.id);
+        for allocation in &order.fulfilment {
+            if let Some(warehouse) = inv.get_mut(&allocation.warehouse) {
+                warehouse.inventory.deduct_stock(&allocation.sku, allocation.quantity)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn expire_reservations(inventory: &Arc<Mutex<Warehouses>>) -> Result<()> {
+    let mut inv = lock_inventory(inventory)?;
+    for (warehouse, order_id, reservation) in inv.expire()? {
+        let lines: Vec<String> = reservation.lines.iter().map(|line| line.to_string()).collect();
+        println!("[Order ID {}] Reservation of {} at {} expired and was released", order_id, lines.join(", "), warehouse);
+    }
+    Ok(())
+}
+
+// Reserve the stock of every line of the order and pass it on to delivery, when the
+// strategy finds enough for all of them. The warehouses shipping it are recorded on
+// the order.
+fn try_reserve(transport: &dyn MessageTransport, inv: &mut Warehouses, order: &mut Order, strategy: FulfilmentStrategy) -> Result<bool> {
+    let Some(allocations) = inv.allocate(order, strategy) else {
+        return Ok(false);
+    };
+    if !inv.reserve(order.id, &allocations)? {
+        return Ok(false);
+    }
+    order.transition(OrderStatus::Reserved)?;
+    order.fulfilment = allocations;
+    let shipped_from: Vec<String> = order.fulfilment.iter().map(|a| a.to_string()).collect();
+    println!("[Order ID {}] Order processed successfully - Shipping: {}", order.id, shipped_from.join(", "));
+    report(transport, order, SagaEventKind::StepCompleted(SagaStep::ReserveStock))?;
+    send_queue(transport, order, "delivery")?;
+
+    for allocation in &order.fulfilment {
+        place_purchase_order(inv, &allocation.warehouse, &allocation.sku, 0)?;
+    }
+    Ok(true)
+}
+
+fn place_purchase_order(inv: &mut Warehouses, warehouse: &str, item: &str, demand: i32) -> Result<()> {
+    let Some(warehouse) = inv.get_mut(warehouse) else {
+        return Ok(());
+    };
+    if let Some(po) = warehouse.inventory.replenish(item, demand)? {
+        println!(
+            "[Purchase order {}] {} ordered {} {} from the supplier, arriving in {}ms",
+            po.id, warehouse.name, po.quantity, po.sku, warehouse.inventory.lead_time.as_millis()
+        );
+    }
+    Ok(())
+}
+
+// Order stock for the backorders waiting on `item` at the warehouses serving them
+fn replenish_backorders(inv: &mut Warehouses, item: &str, backorders: &BackorderStore) -> Result<()> {
+    let mut demand: Vec<(String, i32)> = Vec::new();
+    for order in backorders.orders(item) {
+        let home = inv.home(&order.shipping_address.state).name.clone();
+        let quantity = order.quantity_of(item);
+        match demand.iter_mut().find(|(warehouse, _)| *warehouse == home) {
+            Some((_, total)) => *total += quantity,
+            None => demand.push((home, quantity)),
+        }
+    }
+    for (warehouse, quantity) in demand {
+        place_purchase_order(inv, &warehouse, item, quantity)?;
+    }
+    Ok(())
+}
+
+// An order is reserved all-or-nothing: when any line finds too little stock, the whole
+// order is held in `backorders` or failed, according to the stockout policy. Either
+// way stock is ordered from the supplier once it runs low.
+pub fn inventory_checking(
+    transport: &dyn MessageTransport,
+    inventory: &Arc<Mutex<Warehouses>>,
+    order: &mut Order,
+    settings: &InventoryConfig,
+    backorders: &mut BackorderStore,
+) -> Result<()> {
+    let mut inv = lock_inventory(inventory)?;
     println!("------------------------------------------------------------------");
-    println!("[Order ID {}] Inventory system received order, Item: {}, Quantity: {}", order.id, order.item, order.quantity);
-    if inv.is_stock_available(&order.item, order.quantity) {
-        inv.deduct_stock(&order.item, order.quantity);
-        println!("[Order ID {}] Order processed successfully - Remaining stock: {}", order.id, inv.get_stock(&order.item));
-        send_queue(order, "delivery");
-    } else {
-        println!("[Order ID {}] Insufficient stock, restock processing...", order.id);
-        inv.restock(&order.item);
-        if inv.is_stock_available(&order.item, order.quantity) {
-            inv.deduct_stock(&order.item, order.quantity);
-            println!("[Order ID {}] Order processed - Item: {}, Quantity: {}, Remaining stock: {}", order.id, order.item, order.quantity, inv.get_stock(&order.item));
-            send_queue(order, "delivery");
-        } else {
-            println!("[Order ID {}] Order processing failed - Insufficient stock after restocking.", order.id);
+    println!("[Order ID {}] Inventory system received order, Items: {}", order.id, order.describe_lines());
+    // Orders already waiting for any of these items are served first
+    let queued = order.lines.iter().any(|line| backorders.demand(&line.sku) > 0);
+    if !queued && try_reserve(transport, &mut inv, order, settings.fulfilment)? {
+        return Ok(());
+    }
+
+    for line in &order.lines {
+        println!("[Order ID {}] Requested {}, available {}", order.id, line, inv.available(&line.sku));
+    }
+    let home = inv.home(&order.shipping_address.state).name.clone();
+    let unfillable = inv.unfillable_line(order, settings.fulfilment).cloned();
+    if settings.stockout == StockoutPolicy::Fail || unfillable.is_some() {
+        for line in &order.lines {
+            place_purchase_order(&mut inv, &home, &line.sku, 0)?;
         }
+        println!("[Order ID {}] Order processing failed - Insufficient stock.", order.id);
+        let reason = match unfillable {
+            Some(line) => format!("{} is more than the warehouses ever stock", line),
+            None => {
+                let short: Vec<String> = order
+                    .lines
+                    .iter()
+                    .filter(|line| inv.available(&line.sku) < order.quantity_of(&line.sku))
+                    .map(|line| format!("{} ({} available)", line, inv.available(&line.sku)))
+                    .collect();
+                format!("insufficient stock for {}", short.join(", "))
+            }
+        };
+        return report(transport, order, SagaEventKind::StepFailed(SagaStep::ReserveStock, reason));
+    }
+
+    println!("[Order ID {}] Backordered until stock arrives", order.id);
+    backorders.push(order.clone());
+    for line in &order.lines {
+        replenish_backorders(&mut inv, &line.sku, backorders)?;
     }
+    Ok(())
+}
+
+// Put the stock of arrived purchase orders on the shelf
+pub fn receive_stock(inventory: &Arc<Mutex<Warehouses>>) -> Result<()> {
+    let mut inv = lock_inventory(inventory)?;
+    for (warehouse, po) in inv.receive_due()? {
+        println!("[Purchase order {}] {} received {} {}", po.id, warehouse, po.quantity, po.sku);
+    }
+    Ok(())
+}
+
+// Fill the backorders that stock now covers, oldest first per item, whether the stock
+// came from a purchase order or from a released reservation. Items still short of
+// their backorders are ordered again.
+pub fn release_backorders(
+    transport: &dyn MessageTransport,
+    inventory: &Arc<Mutex<Warehouses>>,
+    strategy: FulfilmentStrategy,
+    backorders: &mut BackorderStore,
+) -> Result<()> {
+    let mut inv = lock_inventory(inventory)?;
+    while let Some(mut backorder) = backorders.pop_ready(|order| inv.allocate(order, strategy).is_some()) {
+        println!(
+            "[Order ID {}] Releasing backorder after {}ms",
+            backorder.order.id,
+            backorder.since.elapsed().as_millis()
+        );
+        try_reserve(transport, &mut inv, &mut backorder.order, strategy)?;
+    }
+    for item in backorders.items() {
+        replenish_backorders(&mut inv, &item, backorders)?;
+    }
+    Ok(())
+}
+
+// Give up on backorders that have waited too long; the saga cancels them
+pub fn expire_backorders(transport: &dyn MessageTransport, backorders: &mut BackorderStore) -> Result<()> {
+    for backorder in backorders.expire() {
+        let order = backorder.order;
+        let reason = format!("backordered for {}ms without stock arriving", backorder.since.elapsed().as_millis());
+        println!("[Order ID {}] Cancelling backorder: {}", order.id, reason);
+        report(transport, &order, SagaEventKind::StepFailed(SagaStep::ReserveStock, reason))?;
+    }
+    Ok(())
 }
 
 // Delivery system functions
-pub fn process_delivery(order: &mut Order) {
+pub fn process_delivery(transport: &dyn MessageTransport, routes: &RouteTable, board: &mut DispatchBoard, order: &mut Order) -> Result<()> {
     println!("[Order ID {}] Delivery system received order", order.id);
-    println!("[Order ID {}] Shipping to the address in the order...", order.id);
+    let shipped_from: Vec<String> = order.fulfilment.iter().map(|a| a.warehouse.clone()).collect();
+    println!("[Order ID {}] Shipping from {} to the address in the order...", order.id, shipped_from.join(" and "));
+
+    // Allocate courier based on the routing rules and book it on a dispatch run before
+    // the order is marked shipped, so an order that cannot go out is left as it arrived
+    let courier = allocate_courier(routes, order)?;
+    println!("[Order ID {}] Shipping location: {} is allocated Courier as {}", order.id, order.shipping_address.state, courier);
+    let settings = config::get();
+    let booking = book_dispatch(board, order, courier)?;
+    let shipment = Shipment::quote(order, &booking, catalog::get(), &settings.inventory.warehouses, &settings.shipping)?;
+    order.transition(OrderStatus::Shipped)?;
+    report(transport, order, SagaEventKind::StepCompleted(SagaStep::Ship))?;
+    for consignment in &shipment.consignments {
+        println!("[Order ID {}] Consignment: {}", order.id, consignment);
+    }
+    println!("[Order ID {}] Shipping cost: {}, estimated delivery: {}", order.id, shipment.cost, format_date(shipment.estimated_delivery));
+    let (stages, attempt) = shipment.first_attempt(settings.shipping.rate(booking.mode));
+    let dispatched_at = shipment.dispatched_at;
+    order.shipment = Some(shipment);
+
+    // Simulated stages up to the courier's doorstep, each published for tracking
+    let address = order.shipping_address.locality();
+    let mut origins = shipped_from.clone();
+    origins.dedup();
+    track(transport, order, TrackingStage::LabelCreated, dispatched_at, &origins.join(" and "))?;
+    for (stage, at) in stages {
+        let location = match stage {
+            TrackingStage::PickedUp => origins.join(" and "),
+            TrackingStage::InTransit => format!("by {} to {}", booking.mode, address),
+            _ => address.clone(),
+        };
+        track(transport, order, stage, at, &location)?;
+    }
     let mut rng = rand::thread_rng();
 
     // Simulate a delivery process with a 50% success rate
     if rng.gen_bool(0.5) {
-        // Allocate courier based on the shipping address
-        let courier = allocate_courier(&order.shipping_address);
-        println!("[Order ID {}] Shipping location: {} is allocated Courier as {}", order.id, order.shipping_address, courier);
-        order.delivery_status = true;
+        track(transport, order, TrackingStage::Delivered, attempt, &address)?;
+        order.transition(OrderStatus::Delivered)?;
         println!("[Order ID {}] Deliver successfully!", order.id);
-        order.final_status = "Delivered".to_string();
+        report(transport, order, SagaEventKind::StepCompleted(SagaStep::Deliver))?;
         //Send the order to the database system
-        send_queue(&order, "database");
+        send_queue(transport, order, "database")?;
         println!("[Order ID {}] Recording to the database!", order.id);
     } else {
-        order.delivery_status = false;
+        track(transport, order, TrackingStage::FailedAttempt, attempt, &address)?;
+        order.transition(OrderStatus::DeliveryFailed)?;
         println!("[Order ID {}] Failure delivery!", order.id);
         //Send the order to the monitoring system
-        send_queue(&order, "monitoring");
+        send_queue(transport, order, "monitoring")?;
         println!("[Order ID {}] Send to monitoring system!", order.id);
     }
 
     println!("---------------------------------------------");
+    Ok(())
 }
 
-pub fn allocate_courier(shipping_address: &str) -> &'static str {
-    match shipping_address {
-        // Peninsular Malaysia - Land transportation
-        "Johor" | "Kedah" | "Kelantan" | "Kuala Lumpur" | "Melaka" |
-        "Negeri Sembilan" | "Pahang" | "Penang" | "Perak" | "Perlis" |
-        "Putrajaya" | "Selangor" | "Terengganu" => "Land",
+// Book the order on the first dispatch run with room once its parcels are ready,
+// overflowing to the courier's later runs or its alternates when a run is full
+fn book_dispatch(board: &mut DispatchBoard, order: &Order, mode: TransportMode) -> Result<Booking> {
+    let ready_at = unix_millis() + config::get().shipping.rate(mode).pickup_hours as u64 * HOUR_MS;
+    let booking = board
+        .book(order.id, mode, Zone::of(&order.shipping_address.state), ready_at)
+        .ok_or_else(|| Error::Dispatch(format!("no {} run with room for order {}", mode, order.id)))?;
 
-        // East Malaysia - Sea transportation
-        "Sabah" | "Sarawak" | "Labuan" => "Sea",
+    let first_choice = board.courier_for(mode).map(|courier| courier.name.clone()).unwrap_or_default();
+    if booking.courier != first_choice {
+        println!("[Order ID {}] {} is full, overflowing to {}", order.id, first_choice, booking);
+    } else {
+        println!("[Order ID {}] Booked on {}", order.id, booking);
+    }
+    Ok(booking)
+}
+
+// Move the order's shipment to the next stage and publish the event on the tracking
+// queue; orders that were never shipped have nothing to track
+fn track(transport: &dyn MessageTransport, order: &mut Order, stage: TrackingStage, at: u64, location: &str) -> Result<()> {
+    let Some(shipment) = order.shipment.as_mut() else {
+        return Ok(());
+    };
+    let event = shipment.advance(order.id, stage, at, location);
+    println!("[Order ID {}] Tracking: {} at {}", order.id, stage, format_time(at));
+    send_envelope(transport, &Envelope::Message(&event), "tracking")
+}
 
-        // Default to air transportation if not recognized
-        _ => "Air",
+// Pick the transport mode with the first routing rule matching the order, falling
+// back to air when no rule does. Orders whose address does not validate are refused
+// rather than routed on a state the rules cannot know.
+pub fn allocate_courier(routes: &RouteTable, order: &Order) -> Result<TransportMode> {
+    order.shipping_address.validate()?;
+    match routes.route(&Parcel::of(order, catalog::get())) {
+        Some(rule) => {
+            println!("[Order ID {}] Routing rule on line {} matched", order.id, rule.line);
+            Ok(rule.mode)
+        }
+        None => {
+            println!("[Order ID {}] No routing rule matched {}, sending by Air", order.id, order.shipping_address.state);
+            Ok(TransportMode::Air)
+        }
     }
 }
 
 // Monitoring system functions
-pub fn repayment(order: &mut Order) {
-    println!("[Order ID {}] Attempting to process payment again.......", order.id);
-    let mut rng = rand::thread_rng();
+// A failed retry goes back to the monitoring system, which decides whether to try again
+pub fn repayment(transport: &dyn MessageTransport, gateway: &dyn PaymentGateway, order: &mut Order) -> Result<()> {
+    order.payment_retries += 1;
+    println!("[Order ID {}] Attempting to process payment again ({}).......", order.id, order.payment_retries);
 
-    // Simulate repayment process with a 50% success rate
-    if rng.gen_bool(0.5) {
-        order.payment_status = true;
+    let outcome = gateway.charge(order);
+    if outcome == PaymentOutcome::Authorised {
+        order.transition(OrderStatus::Paid)?;
         println!("[Order ID {}] Payment is successful!", order.id);
-        send_queue(order, "inventory");
+        report(transport, order, SagaEventKind::StepCompleted(SagaStep::ChargePayment))?;
+        send_queue(transport, order, "inventory")
     } else {
-        order.payment_status = false;
-        println!("[Order ID {}] Payment failed again!", order.id);
-        println!("[Order ID {}] is being canceled due to repeated payment failure.", order.id);
-        cancel_order(order);
+        println!("[Order ID {}] Payment failed again: {}", order.id, outcome);
+        send_queue(transport, order, "monitoring")
     }
 }
 
-pub fn redelivery(order: &mut Order) {
-    println!("[Order ID {}] Attempting to deliver the order again.......", order.id);
+pub fn redelivery(transport: &dyn MessageTransport, order: &mut Order) -> Result<()> {
+    order.delivery_retries += 1;
+    println!("[Order ID {}] Attempting to deliver the order again ({}).......", order.id, order.delivery_retries);
+    let address = order.shipping_address.locality();
+    let attempt = match &order.shipment {
+        Some(shipment) => {
+            let (out_for_delivery, attempt) = shipment.next_attempt(config::get().shipping.rate(shipment.mode));
+            track(transport, order, TrackingStage::OutForDelivery, out_for_delivery, &address)?;
+            attempt
+        }
+        None => unix_millis(),
+    };
     let mut rng = rand::thread_rng();
 
     // Simulate redelivery process with a 50% success rate
     if rng.gen_bool(0.5) {
-        order.delivery_status = true;
-        order.final_status = "Delivered".to_string();
+        track(transport, order, TrackingStage::Delivered, attempt, &address)?;
+        order.transition(OrderStatus::Delivered)?;
         println!("[Order ID {}] The order was delivered successfully!", order.id);
-        send_queue(order, "database");
+        report(transport, order, SagaEventKind::StepCompleted(SagaStep::Deliver))?;
+        send_queue(transport, order, "database")
     } else {
-        order.delivery_status = false;
+        track(transport, order, TrackingStage::FailedAttempt, attempt, &address)?;
         println!("[Order ID {}] The order has not been successfully delivered!", order.id);
-        println!("[Order ID {}] is being canceled due to repeated delivery failure.", order.id);
-        println!("[Order ID {}] Return items back to inventory......", order.id);
-        send_queue(order, "return_inventory");
-        cancel_order(order);
+        send_queue(transport, order, "monitoring")
     }
 }
 
-pub fn cancel_order(order: &mut Order) {
-    order.final_status = "Cancelled".to_string();
-    send_queue(order, "database");
+// Give up on an order the monitoring system has stopped retrying; the saga
+// coordinator cancels it and undoes the steps already completed
+pub fn abandon_order(transport: &dyn MessageTransport, order: &Order, reason: &str) -> Result<()> {
+    let step = if order.status == OrderStatus::DeliveryFailed {
+        println!("[Order ID {}] is being canceled due to repeated delivery failure: {}", order.id, reason);
+        SagaStep::Deliver
+    } else {
+        println!("[Order ID {}] is being canceled due to repeated payment failure: {}", order.id, reason);
+        SagaStep::ChargePayment
+    };
+    report(transport, order, SagaEventKind::StepFailed(step, reason.to_string()))
+}
+
+pub fn process_refund(transport: &dyn MessageTransport, gateway: &dyn PaymentGateway, order: &mut Order) -> Result<()> {
+    println!("[Order ID {}] Payment system received refund", order.id);
+    let refund = gateway.refund(order);
+    match &refund {
+        RefundStatus::Refunded => println!("[Order ID {}] Refunded {}", order.id, order.totals.total),
+        RefundStatus::Failed(reason) => println!("[Order ID {}] Refund failed: {}", order.id, reason),
+    }
+    order.refund = Some(refund);
+    send_queue(transport, order, "database")
 }
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+// Write-ahead log of inventory changes. Each record is one line holding the CRC-32
+// of its JSON body as 8 hex digits, a space, and the JSON body:
+//
+//     89fb1566 {"Deduct":{"sku":"TS-001","quantity":2}}
+//
+// Records are appended and synced to disk before the change is applied in memory.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    // Absolute quantity on hand, written by compaction
+    Set { sku: String, quantity: i32 },
+    Deduct { sku: String, quantity: i32 },
+    Add { sku: String, quantity: i32 },
+    // One item held for an order; an order holding several items has one record per
+    // item. Expiry in milliseconds since the Unix epoch, so it survives a restart
+    Reserve { order_id: i32, sku: String, quantity: i32, expires_at_ms: u64 },
+    Commit { order_id: i32 },
+    Release { order_id: i32 },
+    // Arrival time in milliseconds since the Unix epoch, like Reserve's expiry
+    PurchaseOrder { id: u32, sku: String, quantity: i32, arrives_at_ms: u64 },
+    // The purchase order arrived and its stock is on the shelf
+    Receive { id: u32 },
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(String, io::Error),
+    // A record before the last one failed its checksum or could not be parsed
+    Corrupt { path: String, line: usize, reason: String },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(path, e) => write!(f, "cannot access journal {}: {}", path, e),
+            JournalError::Corrupt { path, line, reason } => write!(f, "journal {} is corrupt at line {}: {}", path, line, reason),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+pub struct Journal {
+    path: String,
+    file: File,
+}
+
+impl Journal {
+    // Read every intact record of the journal at `path`, empty if it does not exist.
+    // A damaged final record is a write cut short by a crash and is dropped; damage
+    // anywhere else means the file cannot be trusted.
+    pub fn read(path: &str) -> Result<Vec<JournalEntry>, JournalError> {
+        if !Path::new(path).exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(path).map_err(|e| JournalError::Io(path.to_string(), e))?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let complete = contents.ends_with('\n');
+
+        let mut entries = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            match decode(line) {
+                Ok(entry) => entries.push(entry),
+                Err(reason) if index + 1 == lines.len() && !complete => {
+                    println!("[Journal] Dropping incomplete last record of {}: {}", path, reason);
+                }
+                Err(reason) => {
+                    return Err(JournalError::Corrupt { path: path.to_string(), line: index + 1, reason });
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    // Replace the journal with `entries` (normally a snapshot of the current state)
+    // and open it for appending. The new file is written aside and renamed into
+    // place, so a crash leaves either the old or the new journal.
+    pub fn rewrite(path: &str, entries: &[JournalEntry]) -> Result<Journal, JournalError> {
+        let io_error = |e| JournalError::Io(path.to_string(), e);
+        let temp = format!("{}.tmp", path);
+        {
+            let file = File::create(&temp).map_err(io_error)?;
+            let mut writer = BufWriter::new(&file);
+            for entry in entries {
+                writer.write_all(encode(entry).as_bytes()).map_err(io_error)?;
+            }
+            writer.flush().map_err(io_error)?;
+            drop(writer);
+            file.sync_all().map_err(io_error)?;
+        }
+        fs::rename(&temp, path).map_err(io_error)?;
+
+        let file = OpenOptions::new().append(true).open(path).map_err(io_error)?;
+        Ok(Journal { path: path.to_string(), file })
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        let io_error = |e| JournalError::Io(self.path.clone(), e);
+        self.file.write_all(encode(entry).as_bytes()).map_err(io_error)?;
+        self.file.sync_data().map_err(io_error)
+    }
+}
+
+fn encode(entry: &JournalEntry) -> String {
+    // Serializing a JournalEntry cannot fail: it only holds strings and integers
+    let body = serde_json::to_string(entry).expect("journal entry serializes");
+    format!("{:08x} {}\n", crc32(body.as_bytes()), body)
+}
+
+fn decode(line: &str) -> Result<JournalEntry, String> {
+    let (checksum, body) = line.split_once(' ').ok_or("missing checksum")?;
+    let expected = u32::from_str_radix(checksum, 16).map_err(|_| format!("invalid checksum {:?}", checksum))?;
+    let actual = crc32(body.as_bytes());
+    if expected != actual {
+        return Err(format!("checksum mismatch, expected {:08x} but found {:08x}", expected, actual));
+    }
+    serde_json::from_str(body).map_err(|e| e.to_string())
+}
+
+// CRC-32 (IEEE 802.3), as used by zip and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Inventory;
+
+    // A journal path of the test's own in the temp directory, with nothing left from a previous run
+    fn journal_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("journal-{}-{}.log", std::process::id(), name));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn entries() -> Vec<JournalEntry> {
+        vec![
+            JournalEntry::Deduct { sku: "TS-001".to_string(), quantity: 2 },
+            JournalEntry::Reserve { order_id: 7, sku: "TS-001".to_string(), quantity: 1, expires_at_ms: 1_792_368_000_000 },
+            JournalEntry::Release { order_id: 7 },
+        ]
+    }
+
+    #[test]
+    fn computes_the_standard_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn reads_back_what_was_appended() {
+        let path = journal_path("append");
+        let mut journal = Journal::rewrite(&path, &[]).unwrap();
+        for entry in entries() {
+            journal.append(&entry).unwrap();
+        }
+        drop(journal);
+        assert_eq!(Journal::read(&path).unwrap(), entries());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_a_torn_last_record() {
+        let path = journal_path("torn");
+        let mut contents: String = entries().iter().map(encode).collect();
+        let torn = encode(&JournalEntry::Commit { order_id: 8 });
+        contents.push_str(&torn[..torn.len() / 2]);
+        fs::write(&path, contents).unwrap();
+        assert_eq!(Journal::read(&path).unwrap(), entries());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_checksum_mismatch_before_the_last_record() {
+        let path = journal_path("corrupt");
+        let lines: Vec<String> = entries().iter().map(encode).collect();
+        let tampered = lines[1].replace("\"quantity\":1", "\"quantity\":9");
+        assert_ne!(tampered, lines[1]);
+        fs::write(&path, [lines[0].clone(), tampered, lines[2].clone()].concat()).unwrap();
+        match Journal::read(&path) {
+            Err(JournalError::Corrupt { line, reason, .. }) => {
+                assert_eq!(line, 2);
+                assert!(reason.contains("checksum mismatch"), "{}", reason);
+            }
+            other => panic!("expected a corrupt journal, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replays_and_compacts_the_journal_on_restart() {
+        let path = journal_path("replay");
+        let mut inventory = Inventory::new().with_journal(&path).unwrap();
+        let sku = inventory.stocks[0].sku.clone();
+        let on_hand = inventory.on_hand(&sku);
+        inventory.deduct_stock(&sku, 3).unwrap();
+        assert!(inventory.reserve(1, &sku, 2).unwrap());
+        assert!(inventory.reserve(2, &sku, 1).unwrap());
+        inventory.release(2).unwrap();
+        drop(inventory);
+
+        let restarted = Inventory::new().with_journal(&path).unwrap();
+        assert_eq!(restarted.on_hand(&sku), on_hand - 3);
+        assert_eq!(restarted.reserved(&sku), 2);
+        assert_eq!(restarted.held(1, &sku), 2);
+
+        // The journal now holds the snapshot of the recovered state and nothing else
+        let compacted = Journal::read(&path).unwrap();
+        assert_eq!(compacted.len(), restarted.stocks.len() + 1);
+        assert!(compacted.iter().all(|entry| matches!(entry, JournalEntry::Set { .. } | JournalEntry::Reserve { .. })));
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
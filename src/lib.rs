@@ -1,4 +1,23 @@
+pub mod config;
+pub mod catalog;
+pub mod error;
+pub mod dead_letter;
+pub mod journal;
+pub mod money;
+pub mod pricing;
+pub mod address;
 pub mod structs;
+pub mod replenishment;
+pub mod backorder;
+pub mod warehouse;
+pub mod payment;
+pub mod retry;
+pub mod routing;
+pub mod dispatch;
+pub mod shipping;
+pub mod tracking;
+pub mod saga;
 pub mod rabbitmq;
+pub mod transport;
 pub mod functions;
-
+pub mod services;
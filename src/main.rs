@@ -1,3 +1,205 @@
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rts_assignment::{
+    config,
+    error,
+    payment::{self, PaymentGateway},
+    services::{
+        run_database, run_delivery, run_inventory, run_monitor, run_order, run_payment, run_refunds, run_saga, run_tracking, Summary,
+    },
+    tracking::TrackingLog,
+    transport::{InMemoryTransport, MessageTransport, RabbitTransport},
+};
+
+// Times a crashed service is restarted before the orchestrator gives up on it
+const MAX_RESTARTS: usize = 3;
+
+type Service = Box<dyn Fn(Arc<dyn MessageTransport>) -> error::Result<()> + Send>;
+type PaymentService = fn(Arc<dyn MessageTransport>, Arc<dyn PaymentGateway>) -> error::Result<()>;
+
+const USAGE: &str = "Usage: rts_assignment [--transport memory|rabbitmq] [--orders N] [--interval-ms MS] [--track ORDER_ID]...";
+
+struct Options {
+    broker: bool,
+    orders: i32,
+    interval: Duration,
+    // Orders whose tracking history is printed at the end
+    track: Vec<i32>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options { broker: false, orders: 10, interval: Duration::from_millis(100), track: Vec::new() };
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--transport" => match value()?.as_str() {
+                "memory" => options.broker = false,
+                "rabbitmq" => options.broker = true,
+                other => return Err(format!("Unknown transport: {}", other)),
+            },
+            "--orders" => {
+                let orders = value()?;
+                options.orders = orders.parse().map_err(|_| format!("Invalid order count: {}", orders))?;
+            }
+            "--interval-ms" => {
+                let interval = value()?;
+                let millis = interval.parse().map_err(|_| format!("Invalid interval: {}", interval))?;
+                options.interval = Duration::from_millis(millis);
+            }
+            "--track" => {
+                let order_id = value()?;
+                options.track.push(order_id.parse().map_err(|_| format!("Invalid order id: {}", order_id))?);
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+// Run a service on its own thread, restarting it if it panics or stops with an error.
+// The service keeps what it must not lose (next order id, summary, tracking log) in
+// `state`, which is handed to each restart and returned once the service finishes.
+fn supervise<S, F>(name: &'static str, mut state: S, service: F) -> JoinHandle<Option<S>>
+where
+    S: Send + 'static,
+    F: Fn(&mut S) -> error::Result<()> + Send + 'static,
+{
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            for attempt in 0..=MAX_RESTARTS {
+                if attempt > 0 {
+                    println!("[Supervisor] Restarting {} service ({}/{})", name, attempt, MAX_RESTARTS);
+                }
+                match panic::catch_unwind(AssertUnwindSafe(|| service(&mut state))) {
+                    Ok(Ok(())) => return Some(state),
+                    Ok(Err(e)) => println!("[Supervisor] {} service stopped: {}", name, e),
+                    Err(_) => println!("[Supervisor] {} service crashed", name),
+                }
+            }
+            println!("[Supervisor] Giving up on {} service", name);
+            None
+        })
+        .expect("failed to spawn service thread")
+}
+
 fn main() {
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    let config = config::init_from_env();
+
+    let transport: Arc<dyn MessageTransport> = if options.broker {
+        println!("Running the pipeline through the broker at {}:{}", config.broker.host, config.broker.port);
+        Arc::new(RabbitTransport::new(&config.broker))
+    } else {
+        println!("Running the pipeline with in-process queues");
+        Arc::new(InMemoryTransport::new())
+    };
+
+    // Start the consumers first so nothing is published to a queue without a reader
+    let database = {
+        let transport = Arc::clone(&transport);
+        let summary = Summary::new(config.pricing.currency);
+        supervise("database", summary, move |summary| run_database(Arc::clone(&transport), summary))
+    };
+    let tracking = {
+        let transport = Arc::clone(&transport);
+        supervise("tracking", TrackingLog::default(), move |log| run_tracking(Arc::clone(&transport), log))
+    };
+    // One gateway shared by payment and monitoring, and kept across restarts, so
+    // scripted outcomes and seeded draws are each used once
+    let gateway = payment::from_config(&config.payment);
+    let with_gateway = |run: PaymentService| -> Service {
+        let gateway = Arc::clone(&gateway);
+        Box::new(move |transport| run(transport, Arc::clone(&gateway)))
+    };
+    let mut services = Vec::new();
+    let stages: [(&'static str, Service); 6] = [
+        ("refund", with_gateway(run_refunds)),
+        ("monitor", with_gateway(run_monitor)),
+        ("delivery", Box::new(run_delivery)),
+        ("inventory", Box::new(run_inventory)),
+        ("payment", with_gateway(run_payment)),
+        ("saga", Box::new(run_saga)),
+    ];
+    for (name, run) in stages {
+        let transport = Arc::clone(&transport);
+        services.push((name, supervise(name, (), move |_| run(Arc::clone(&transport)))));
+    }
+
+    let (orders, interval) = (options.orders, options.interval);
+    let order_transport = Arc::clone(&transport);
+    let order = supervise("order", 1, move |next_id| run_order(Arc::clone(&order_transport), next_id, orders, interval));
+
+    if order.join().is_err() {
+        println!("[Supervisor] order service thread failed");
+    }
+    for (name, handle) in services {
+        if handle.join().is_err() {
+            println!("[Supervisor] {} service thread failed", name);
+        }
+    }
+
+    println!("==================== Simulation summary ====================");
+    println!("Orders generated: {}", options.orders);
+    match database.join() {
+        Ok(Some(summary)) => print!("{}", summary),
+        _ => println!("The database service did not complete, no summary available"),
+    }
+    match tracking.join() {
+        Ok(Some(log)) => options.track.iter().for_each(|&order_id| print_history(&log, order_id)),
+        _ => println!("The tracking service did not complete, no tracking history available"),
+    }
+}
+
+fn print_history(log: &TrackingLog, order_id: i32) {
+    println!("Tracking history of order {}:", order_id);
+    let history = log.history(order_id);
+    if history.is_empty() {
+        println!("  No tracking events");
+    }
+    for event in history {
+        println!("  {}", event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_a_failing_service_with_its_state() {
+        // Fails twice, then finishes; the attempts are counted in the state
+        let handle = supervise("flaky", 0, |attempts: &mut usize| {
+            *attempts += 1;
+            match *attempts {
+                1 => Err(error::Error::Transport("broker went away".to_string())),
+                2 => panic!("service crashed"),
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(handle.join().unwrap(), Some(3));
+    }
 
+    #[test]
+    fn gives_up_after_the_restart_limit() {
+        let handle = supervise("broken", 0, |attempts: &mut usize| {
+            *attempts += 1;
+            Err(error::Error::Transport("broker went away".to_string()))
+        });
+        assert_eq!(handle.join().unwrap(), None);
+    }
 }
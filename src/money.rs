@@ -0,0 +1,185 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+// ISO 4217 currency codes the system prices in, all with two decimal places
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Currency {
+    #[default]
+    MYR,
+    SGD,
+    USD,
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// An amount in the currency's minor unit (sen for MYR), so arithmetic is exact. On the
+// wire it is a decimal string and a currency code: {"amount":"29.90","currency":"MYR"}
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(try_from = "MoneyRepr", into = "MoneyRepr")]
+pub struct Money {
+    pub minor: i64,
+    pub currency: Currency,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoneyRepr {
+    amount: String,
+    currency: Currency,
+}
+
+impl TryFrom<MoneyRepr> for Money {
+    type Error = String;
+
+    fn try_from(repr: MoneyRepr) -> Result<Self, Self::Error> {
+        Money::parse(&repr.amount, repr.currency)
+    }
+}
+
+impl From<Money> for MoneyRepr {
+    fn from(money: Money) -> Self {
+        MoneyRepr { amount: money.decimal(), currency: money.currency }
+    }
+}
+
+impl Money {
+    pub fn new(minor: i64, currency: Currency) -> Self {
+        Money { minor, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(0, currency)
+    }
+
+    // Parse a decimal amount with at most two decimal places, e.g. "29.90" or "8"
+    pub fn parse(amount: &str, currency: Currency) -> Result<Money, String> {
+        let invalid = || format!("invalid amount {:?}", amount);
+        let (negative, digits) = match amount.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, amount),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            // A decimal point must be followed by one or two digits
+            Some((_, "")) => return Err(invalid()),
+            Some(parts) => parts,
+            None => (digits, ""),
+        };
+        if whole.is_empty() || fraction.len() > 2 || !(whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())) {
+            return Err(invalid());
+        }
+        let whole: i64 = whole.parse().map_err(|_| invalid())?;
+        let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+        let minor = whole.checked_mul(100).and_then(|m| m.checked_add(fraction)).ok_or_else(invalid)?;
+        Ok(Money::new(if negative { -minor } else { minor }, currency))
+    }
+
+    // "29.90"
+    pub fn decimal(&self) -> String {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        format!("{}{}.{:02}", sign, self.minor.abs() / 100, self.minor.abs() % 100)
+    }
+
+    pub fn checked_times(self, quantity: i32) -> Result<Money, MoneyError> {
+        let minor = self.minor.checked_mul(quantity as i64).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch { expected: self.currency, found: other.currency });
+        }
+        let minor = self.minor.checked_add(other.minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    // The sum of `amounts`, all of which must be in `currency`
+    pub fn sum(currency: Currency, amounts: impl IntoIterator<Item = Money>) -> Result<Money, MoneyError> {
+        amounts.into_iter().try_fold(Money::zero(currency), Money::checked_add)
+    }
+
+    // `percent` of the amount, rounded half up to the nearest minor unit
+    pub fn checked_percent(self, percent: u32) -> Result<Money, MoneyError> {
+        let scaled = self.minor.checked_mul(percent as i64).ok_or(MoneyError::Overflow)?;
+        let rounded = scaled.checked_add(scaled.signum() * 50).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(rounded / 100, self.currency))
+    }
+}
+
+// Arithmetic on amounts that cannot give a right answer. Amounts arrive in messages
+// and config, so these are reported rather than trusted never to happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    // Amounts in different currencies cannot be added
+    CurrencyMismatch { expected: Currency, found: Currency },
+    // The result does not fit in an i64 of minor units
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch { expected, found } => write!(f, "cannot add an amount in {} to one in {}", found, expected),
+            MoneyError::Overflow => write!(f, "amount out of range"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.currency, self.decimal())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn myr(minor: i64) -> Money {
+        Money::new(minor, Currency::MYR)
+    }
+
+    #[test]
+    fn adds_amounts_in_the_same_currency_only() {
+        assert_eq!(myr(2990).checked_add(myr(800)), Ok(myr(3790)));
+        assert_eq!(
+            myr(2990).checked_add(Money::new(500, Currency::SGD)),
+            Err(MoneyError::CurrencyMismatch { expected: Currency::MYR, found: Currency::SGD })
+        );
+        assert_eq!(Money::sum(Currency::MYR, [myr(100), myr(250)]), Ok(myr(350)));
+        assert!(Money::sum(Currency::USD, [myr(100)]).is_err());
+    }
+
+    #[test]
+    fn reports_overflow_instead_of_wrapping() {
+        assert_eq!(myr(i64::MAX).checked_add(myr(1)), Err(MoneyError::Overflow));
+        assert_eq!(myr(i64::MAX / 2).checked_times(3), Err(MoneyError::Overflow));
+        assert_eq!(myr(i64::MAX / 10).checked_percent(110), Err(MoneyError::Overflow));
+        assert_eq!(myr(2990).checked_times(3), Ok(myr(8970)));
+    }
+
+    #[test]
+    fn rounds_percentages_half_away_from_zero() {
+        assert_eq!(myr(1005).checked_percent(10), Ok(myr(101)));
+        assert_eq!(myr(1004).checked_percent(10), Ok(myr(100)));
+        assert_eq!(myr(-1005).checked_percent(10), Ok(myr(-101)));
+        assert_eq!(myr(3300).checked_percent(150), Ok(myr(4950)));
+    }
+
+    #[test]
+    fn parses_decimal_amounts() {
+        let parse = |amount| Money::parse(amount, Currency::MYR);
+        assert_eq!(parse("29.90"), Ok(myr(2990)));
+        assert_eq!(parse("29.9"), Ok(myr(2990)));
+        assert_eq!(parse("8"), Ok(myr(800)));
+        assert_eq!(parse("-0.05"), Ok(myr(-5)));
+        for invalid in ["1.", ".5", "", "-", "1.234", "1,50", "+1", "1.-2", "99999999999999999999"] {
+            assert!(parse(invalid).is_err(), "{:?} should not parse", invalid);
+        }
+        assert_eq!(parse(&myr(-123).decimal()), Ok(myr(-123)));
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use crate::config::PaymentConfig;
+use crate::money::Money;
+use crate::structs::{Order, OrderLine, RefundStatus};
+
+// Result of asking a gateway to charge an order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "outcome", content = "reason", rename_all = "lowercase")]
+pub enum PaymentOutcome {
+    Authorised,
+    // The gateway refused the charge, e.g. insufficient funds
+    Declined(String),
+    // The gateway could not give an answer, e.g. a timeout or server error
+    Error(String),
+}
+
+impl fmt::Display for PaymentOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentOutcome::Authorised => write!(f, "authorised"),
+            PaymentOutcome::Declined(reason) => write!(f, "declined ({})", reason),
+            PaymentOutcome::Error(reason) => write!(f, "gateway error ({})", reason),
+        }
+    }
+}
+
+pub trait PaymentGateway: Send + Sync {
+    fn charge(&self, order: &Order) -> PaymentOutcome;
+
+    // Reverse an earlier successful charge
+    fn refund(&self, order: &Order) -> RefundStatus;
+}
+
+// Authorises each charge with a fixed probability. A seeded gateway makes the
+// same decisions on every run.
+pub struct RandomGateway {
+    success_rate: f64,
+    rng: Mutex<StdRng>,
+}
+
+impl RandomGateway {
+    pub fn new(success_rate: f64, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        RandomGateway { success_rate: success_rate.clamp(0.0, 1.0), rng: Mutex::new(rng) }
+    }
+}
+
+impl PaymentGateway for RandomGateway {
+    fn charge(&self, _order: &Order) -> PaymentOutcome {
+        let authorised = match self.rng.lock() {
+            Ok(mut rng) => rng.gen_bool(self.success_rate),
+            Err(_) => return PaymentOutcome::Error("random gateway lock poisoned".to_string()),
+        };
+        if authorised {
+            PaymentOutcome::Authorised
+        } else {
+            PaymentOutcome::Declined("card declined".to_string())
+        }
+    }
+
+    // Refunds of authorised charges always go through
+    fn refund(&self, _order: &Order) -> RefundStatus {
+        RefundStatus::Refunded
+    }
+}
+
+// Replays a fixed list of outcomes in order, then reports an error once it runs out.
+// Refunds replay their own list and succeed once it runs out.
+pub struct ScriptedGateway {
+    outcomes: Mutex<VecDeque<PaymentOutcome>>,
+    refunds: Mutex<VecDeque<RefundStatus>>,
+}
+
+impl ScriptedGateway {
+    pub fn new(outcomes: Vec<PaymentOutcome>) -> Self {
+        Self::with_refunds(outcomes, Vec::new())
+    }
+
+    pub fn with_refunds(outcomes: Vec<PaymentOutcome>, refunds: Vec<RefundStatus>) -> Self {
+        ScriptedGateway { outcomes: Mutex::new(outcomes.into()), refunds: Mutex::new(refunds.into()) }
+    }
+}
+
+impl PaymentGateway for ScriptedGateway {
+    fn charge(&self, _order: &Order) -> PaymentOutcome {
+        match self.outcomes.lock() {
+            Ok(mut outcomes) => outcomes
+                .pop_front()
+                .unwrap_or_else(|| PaymentOutcome::Error("payment script exhausted".to_string())),
+            Err(_) => PaymentOutcome::Error("scripted gateway lock poisoned".to_string()),
+        }
+    }
+
+    fn refund(&self, _order: &Order) -> RefundStatus {
+        match self.refunds.lock() {
+            Ok(mut refunds) => refunds.pop_front().unwrap_or(RefundStatus::Refunded),
+            Err(_) => RefundStatus::Failed("scripted gateway lock poisoned".to_string()),
+        }
+    }
+}
+
+// Charges through a gateway speaking plain HTTP, such as the payment_stub binary.
+// POSTs the order as JSON to /charge: 2xx authorises, 402 declines with the response
+// body as the reason, and any other status, a timeout or a connection failure is an error.
+// Refunds are POSTed to /refund and only a 2xx response counts as refunded.
+pub struct HttpGateway {
+    address: String,
+    timeout: Duration,
+}
+
+#[derive(Serialize)]
+struct GatewayRequest<'a> {
+    order_id: i32,
+    lines: &'a [OrderLine],
+    // Charged once for all lines
+    amount: Money,
+}
+
+impl HttpGateway {
+    pub fn new(address: &str, timeout: Duration) -> Self {
+        HttpGateway { address: address.to_string(), timeout }
+    }
+
+    fn resolve(&self) -> Result<SocketAddr, String> {
+        self.address
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {}", self.address, e))?
+            .next()
+            .ok_or_else(|| format!("no address for {}", self.address))
+    }
+
+    fn post(&self, path: &str, order: &Order) -> Result<(u16, String), String> {
+        let request = GatewayRequest { order_id: order.id, lines: &order.lines, amount: order.totals.total };
+        let body = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+        let addr = self.resolve()?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| format!("connect: {}", e))?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            self.address,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).map_err(|e| format!("send: {}", e))?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| format!("receive: {}", e))?;
+
+        // "HTTP/1.1 402 Payment Required\r\n...\r\n\r\nbody"
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| "malformed HTTP response".to_string())?;
+        let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body).trim();
+        Ok((status, body.to_string()))
+    }
+}
+
+impl PaymentGateway for HttpGateway {
+    fn charge(&self, order: &Order) -> PaymentOutcome {
+        match self.post("/charge", order) {
+            Ok((200..=299, _)) => PaymentOutcome::Authorised,
+            Ok((402, reason)) => PaymentOutcome::Declined(reason),
+            Ok((status, reason)) => PaymentOutcome::Error(format!("HTTP {}: {}", status, reason)),
+            Err(e) => PaymentOutcome::Error(e),
+        }
+    }
+
+    fn refund(&self, order: &Order) -> RefundStatus {
+        match self.post("/refund", order) {
+            Ok((200..=299, _)) => RefundStatus::Refunded,
+            Ok((status, reason)) => RefundStatus::Failed(format!("HTTP {}: {}", status, reason)),
+            Err(e) => RefundStatus::Failed(e),
+        }
+    }
+}
+
+// Build the gateway selected in the config
+pub fn from_config(config: &PaymentConfig) -> Arc<dyn PaymentGateway> {
+    match config {
+        PaymentConfig::Random { success_rate, seed } => Arc::new(RandomGateway::new(*success_rate, *seed)),
+        PaymentConfig::Scripted { outcomes, refunds } => {
+            Arc::new(ScriptedGateway::with_refunds(outcomes.clone(), refunds.clone()))
+        }
+        PaymentConfig::Http { address, timeout_ms } => {
+            Arc::new(HttpGateway::new(address, Duration::from_millis(*timeout_ms)))
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::catalog::Catalog;
+use crate::money::{Currency, Money, MoneyError};
+use crate::structs::Order;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PricingConfig {
+    // Currency orders are priced and charged in; catalog prices must be in it too
+    pub currency: Currency,
+    // Flat fee charged once per order
+    pub shipping_fee: Money,
+    // Orders whose subtotal reaches this ship free
+    pub free_shipping_from: Option<Money>,
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        PricingConfig {
+            currency: Currency::MYR,
+            shipping_fee: Money::new(800, Currency::MYR),
+            free_shipping_from: Some(Money::new(15000, Currency::MYR)),
+        }
+    }
+}
+
+// What the customer pays for an order
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Totals {
+    // Unit prices times quantities, before tax
+    pub subtotal: Money,
+    // SST of all lines
+    pub tax: Money,
+    pub shipping: Money,
+    pub total: Money,
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subtotal {}, SST {}, shipping {}, total {}", self.subtotal, self.tax, self.shipping, self.total)
+    }
+}
+
+#[derive(Debug)]
+pub enum PricingError {
+    UnknownSku(String),
+    Currency { sku: String, expected: Currency, found: Currency },
+    // The shipping fee is in another currency than the lines, or an amount is out of range
+    Totals(MoneyError),
+}
+
+impl fmt::Display for PricingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PricingError::UnknownSku(sku) => write!(f, "SKU {} is not in the catalog", sku),
+            PricingError::Currency { sku, expected, found } => {
+                write!(f, "SKU {} is priced in {} but orders are charged in {}", sku, found, expected)
+            }
+            PricingError::Totals(e) => write!(f, "cannot total the order: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PricingError {}
+
+// Fill in each line's unit price and SST from the catalog, then the order's totals.
+// SST is worked out per line, rounded to the sen, and the shipping fee is waived once
+// the subtotal reaches the free shipping threshold.
+pub fn price_order(order: &mut Order, catalog: &Catalog, pricing: &PricingConfig) -> Result<(), PricingError> {
+    for line in &mut order.lines {
+        let product = catalog.find(&line.sku).ok_or_else(|| PricingError::UnknownSku(line.sku.clone()))?;
+        if product.price.currency != pricing.currency {
+            return Err(PricingError::Currency { sku: line.sku.clone(), expected: pricing.currency, found: product.price.currency });
+        }
+        line.unit_price = product.price;
+        let price = product.price.checked_times(line.quantity).map_err(PricingError::Totals)?;
+        line.tax = price.checked_percent(product.sst_percent).map_err(PricingError::Totals)?;
+    }
+
+    let currency = pricing.currency;
+    let line_prices = order.lines.iter().map(|line| line.unit_price.checked_times(line.quantity)).collect::<Result<Vec<_>, _>>();
+    let subtotal = line_prices.and_then(|prices| Money::sum(currency, prices)).map_err(PricingError::Totals)?;
+    let tax = Money::sum(currency, order.lines.iter().map(|line| line.tax)).map_err(PricingError::Totals)?;
+    let free_shipping = pricing.free_shipping_from.is_some_and(|threshold| subtotal.minor >= threshold.minor);
+    let shipping = if free_shipping { Money::zero(currency) } else { pricing.shipping_fee };
+    let total = Money::sum(currency, [subtotal, tax, shipping]).map_err(PricingError::Totals)?;
+    order.totals = Totals { subtotal, tax, shipping, total };
+    Ok(())
+}
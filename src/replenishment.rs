@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::journal::{JournalEntry, JournalError};
+use crate::structs::{unix_millis, Inventory};
+
+pub const DEFAULT_LEAD_TIME: Duration = Duration::from_secs(2);
+
+// What happens to an order that finds too little stock
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StockoutPolicy {
+    // Hold the order until a purchase order brings in enough stock
+    #[default]
+    Backorder,
+    // Cancel the order straight away
+    Fail,
+}
+
+// Stock ordered from the supplier, arriving after the lead time
+#[derive(Debug, Clone)]
+pub struct PurchaseOrder {
+    pub id: u32,
+    pub sku: String,
+    pub quantity: i32,
+    pub arrives_at: Instant,
+}
+
+impl Inventory {
+    pub fn with_lead_time(mut self, lead_time: Duration) -> Self {
+        self.lead_time = lead_time;
+        self
+    }
+
+    pub fn on_order(&self, item: &str) -> i32 {
+        self.purchase_orders.iter().filter(|po| po.sku == item).map(|po| po.quantity).sum()
+    }
+
+    // Available now plus everything on order from the supplier
+    pub fn stock_position(&self, item: &str) -> i32 {
+        self.available(item) + self.on_order(item)
+    }
+
+    // Place a purchase order when the stock position, less `demand` still waiting for
+    // stock, has fallen to the item's reorder point. Enough is ordered to bring the
+    // position back up to the order-up-to level.
+    pub fn replenish(&mut self, item: &str, demand: i32) -> Result<Option<PurchaseOrder>, JournalError> {
+        let Some(stock) = self.stocks.iter().find(|stock| stock.sku == item) else {
+            return Ok(None);
+        };
+        let (reorder_point, order_up_to) = (stock.reorder_point, stock.order_up_to);
+        let position = self.stock_position(item) - demand;
+        if position > reorder_point {
+            return Ok(None);
+        }
+
+        let entry = JournalEntry::PurchaseOrder {
+            id: self.next_purchase_order,
+            sku: item.to_string(),
+            quantity: order_up_to - position,
+            arrives_at_ms: unix_millis() + self.lead_time.as_millis() as u64,
+        };
+        self.record(entry)?;
+        Ok(self.purchase_orders.last().cloned())
+    }
+
+    // Put the stock of every purchase order that has arrived on the shelf
+    pub fn receive_due(&mut self) -> Result<Vec<PurchaseOrder>, JournalError> {
+        let now = Instant::now();
+        let due: Vec<PurchaseOrder> = self.purchase_orders.iter().filter(|po| po.arrives_at <= now).cloned().collect();
+        for po in &due {
+            self.record(JournalEntry::Receive { id: po.id })?;
+        }
+        Ok(due)
+    }
+}
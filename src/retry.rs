@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// How often and how patiently the monitoring system retries one kind of failure
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    // Delay before the first retry, doubled for every retry after it
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    // Fraction of the delay added or removed at random, 0.0 - 1.0
+    pub jitter: f64,
+    // Orders older than this are cancelled instead of retried
+    pub max_age_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetryDecision {
+    // Retry after waiting this long
+    Retry(Duration),
+    GiveUp(String),
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 100,
+            max_delay_ms: 2000,
+            jitter: 0.2,
+            max_age_ms: 60_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Decide on the next retry given the retries made so far and the order's age
+    pub fn decide(&self, retries: u32, age: Option<Duration>) -> RetryDecision {
+        if retries >= self.max_attempts {
+            return RetryDecision::GiveUp(format!("gave up after {} retries", retries));
+        }
+        if let Some(age) = age {
+            if age > Duration::from_millis(self.max_age_ms) {
+                return RetryDecision::GiveUp(format!("order is {}ms old, limit is {}ms", age.as_millis(), self.max_age_ms));
+            }
+        }
+        RetryDecision::Retry(self.backoff(retries))
+    }
+
+    // Exponential backoff capped at max_delay_ms, with jitter applied on top
+    pub fn backoff(&self, retries: u32) -> Duration {
+        let exponential = self.base_delay_ms.saturating_mul(2u64.saturating_pow(retries));
+        let delay = exponential.min(self.max_delay_ms) as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 { rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter) } else { 1.0 };
+        Duration::from_millis((delay * factor) as u64)
+    }
+}
+
+// Items waiting for their retry, each taken out once it is due. Items due at the same
+// time come out in the order they were scheduled.
+#[derive(Debug)]
+pub struct RetrySchedule<T> {
+    // Earliest first
+    entries: Vec<(Instant, T)>,
+}
+
+impl<T> Default for RetrySchedule<T> {
+    fn default() -> Self {
+        RetrySchedule { entries: Vec::new() }
+    }
+}
+
+impl<T> RetrySchedule<T> {
+    pub fn schedule(&mut self, due: Instant, item: T) {
+        let at = self.entries.partition_point(|(other, _)| *other <= due);
+        self.entries.insert(at, (due, item));
+    }
+
+    pub fn next_due(&self) -> Option<Instant> {
+        self.entries.first().map(|(due, _)| *due)
+    }
+
+    // The earliest item, if it is due by `now`
+    pub fn pop_due(&mut self, now: Instant) -> Option<T> {
+        if self.next_due()? > now {
+            return None;
+        }
+        Some(self.entries.remove(0).1)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        self.entries.retain(|(_, item)| keep(item));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Retry policies per failure type
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub payment: RetryPolicy,
+    pub delivery: RetryPolicy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy { max_attempts: 6, base_delay_ms: 100, max_delay_ms: 1000, jitter, max_age_ms: 60_000 }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_cap() {
+        let delays: Vec<u128> = (0..6).map(|retries| policy(0.0).backoff(retries).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        for _ in 0..100 {
+            let delay = policy(0.2).backoff(2).as_millis();
+            assert!((320..=480).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts_or_max_age() {
+        let policy = policy(0.0);
+        assert_eq!(policy.decide(0, None), RetryDecision::Retry(Duration::from_millis(100)));
+        assert_eq!(policy.decide(5, Some(Duration::from_secs(1))), RetryDecision::Retry(Duration::from_millis(1000)));
+        assert!(matches!(policy.decide(6, None), RetryDecision::GiveUp(_)));
+        assert!(matches!(policy.decide(0, Some(Duration::from_secs(61))), RetryDecision::GiveUp(_)));
+    }
+
+    #[test]
+    fn hands_out_retries_once_due_earliest_first() {
+        let start = Instant::now();
+        let mut schedule = RetrySchedule::default();
+        schedule.schedule(start + Duration::from_millis(300), "slow");
+        schedule.schedule(start + Duration::from_millis(100), "fast");
+        schedule.schedule(start + Duration::from_millis(100), "fast too");
+        assert_eq!(schedule.next_due(), Some(start + Duration::from_millis(100)));
+
+        assert_eq!(schedule.pop_due(start), None);
+        let later = start + Duration::from_millis(200);
+        assert_eq!(schedule.pop_due(later), Some("fast"));
+        assert_eq!(schedule.pop_due(later), Some("fast too"));
+        assert_eq!(schedule.pop_due(later), None);
+
+        schedule.retain(|item| *item != "slow");
+        assert!(schedule.is_empty());
+        assert_eq!(schedule.next_due(), None);
+    }
+}
@@ -0,0 +1,419 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while1},
+    character::complete::{char, satisfy, space0, space1},
+    combinator::{all_consuming, cut, map, not, peek, value, verify},
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::{many0, separated_list1},
+    number::complete::double,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    Finish, IResult,
+};
+use serde::{Deserialize, Serialize};
+use crate::address;
+use crate::catalog::Catalog;
+use crate::money::Money;
+use crate::structs::Order;
+
+// Courier routing rules, one per line, tried top to bottom:
+//
+//     # East Malaysia from the peninsula
+//     state in [Sabah, Sarawak] and weight > 5 => Sea
+//     otherwise => Land
+//
+// Conditions test the fields state and warehouse with `in [...]`, and weight (kg),
+// quantity (units) and total (order total) with <, <=, >, >=, = or !=. They combine
+// with and, or, not and parentheses; `otherwise` always matches. States must be
+// Malaysian states or federal territories and numbers must be finite.
+
+// Rules compiled into the binary, used when no rules file is found
+const BUNDLED_ROUTES: &str = include_str!("../routes.rules");
+
+type ParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportMode {
+    Land,
+    Sea,
+    Air,
+}
+
+impl fmt::Display for TransportMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    State,
+    // Every warehouse the order ships from
+    Warehouse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    Weight,
+    Quantity,
+    Total,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Always,
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    In(TextField, Vec<String>),
+    Compare(NumberField, Comparison, f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    // Line of the rules file the rule came from, for logging
+    pub line: usize,
+    pub condition: Condition,
+    pub mode: TransportMode,
+}
+
+// What the rules know about an order being shipped
+#[derive(Debug, Clone)]
+pub struct Parcel<'a> {
+    pub state: &'a str,
+    pub warehouses: Vec<&'a str>,
+    pub weight_kg: f64,
+    pub quantity: i32,
+    pub total: Money,
+}
+
+impl<'a> Parcel<'a> {
+    pub fn of(order: &'a Order, catalog: &Catalog) -> Self {
+        let weight_kg = order
+            .lines
+            .iter()
+            .map(|line| catalog.find(&line.sku).map_or(0.0, |product| product.weight_kg) * line.quantity as f64)
+            .sum();
+        Parcel {
+            state: &order.shipping_address.state,
+            warehouses: order.fulfilment.iter().map(|a| a.warehouse.as_str()).collect(),
+            weight_kg,
+            quantity: order.lines.iter().map(|line| line.quantity).sum(),
+            total: order.totals.total,
+        }
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, parcel: &Parcel) -> bool {
+        let listed = |names: &[String], value: &str| names.iter().any(|name| name.eq_ignore_ascii_case(value));
+        match self {
+            Condition::Always => true,
+            Condition::Not(condition) => !condition.matches(parcel),
+            Condition::And(conditions) => conditions.iter().all(|c| c.matches(parcel)),
+            Condition::Or(conditions) => conditions.iter().any(|c| c.matches(parcel)),
+            Condition::In(TextField::State, names) => listed(names, parcel.state),
+            Condition::In(TextField::Warehouse, names) => {
+                !parcel.warehouses.is_empty() && parcel.warehouses.iter().all(|warehouse| listed(names, warehouse))
+            }
+            Condition::Compare(field, comparison, limit) => {
+                let value = match field {
+                    NumberField::Weight => parcel.weight_kg,
+                    NumberField::Quantity => parcel.quantity as f64,
+                    NumberField::Total => parcel.total.minor as f64 / 100.0,
+                };
+                match comparison {
+                    Comparison::Less => value < *limit,
+                    Comparison::LessOrEqual => value <= *limit,
+                    Comparison::Greater => value > *limit,
+                    Comparison::GreaterOrEqual => value >= *limit,
+                    Comparison::Equal => value == *limit,
+                    Comparison::NotEqual => value != *limit,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RoutingError {
+    Io(String, io::Error),
+    // `text` is the offending line; line and column count from 1
+    Syntax { path: String, line: usize, column: usize, message: String, text: String },
+}
+
+impl fmt::Display for RoutingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutingError::Io(path, e) => write!(f, "cannot read routing rules {}: {}", path, e),
+            RoutingError::Syntax { path, line, column, message, text } => {
+                let gutter = line.to_string().len();
+                writeln!(f, "{}:{}:{}: {}", path, line, column, message)?;
+                writeln!(f, "{:>gutter$} |", "")?;
+                writeln!(f, "{} | {}", line, text)?;
+                write!(f, "{:>gutter$} | {:>column$}", "", "^")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoutingError {}
+
+#[derive(Debug, Clone)]
+pub struct RouteTable {
+    pub rules: Vec<Rule>,
+}
+
+impl Default for RouteTable {
+    fn default() -> Self {
+        RouteTable::parse("<bundled>", BUNDLED_ROUTES).expect("bundled routes.rules is invalid")
+    }
+}
+
+impl RouteTable {
+    // Parse the rules in `source`; `path` only names it in error messages
+    pub fn parse(path: &str, source: &str) -> Result<RouteTable, RoutingError> {
+        let mut rules = Vec::new();
+        for (index, text) in source.lines().enumerate() {
+            let code = text.split_once('#').map_or(text, |(code, _)| code);
+            if code.trim().is_empty() {
+                continue;
+            }
+            match all_consuming(rule)(code).finish() {
+                Ok((_, (condition, mode))) => rules.push(Rule { line: index + 1, condition, mode }),
+                Err(e) => {
+                    let (rest, message) = describe(&e);
+                    return Err(RoutingError::Syntax {
+                        path: path.to_string(),
+                        line: index + 1,
+                        column: code.len() - rest.len() + 1,
+                        message,
+                        text: text.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(RouteTable { rules })
+    }
+
+    pub fn from_file(path: &str) -> Result<RouteTable, RoutingError> {
+        let source = fs::read_to_string(path).map_err(|e| RoutingError::Io(path.to_string(), e))?;
+        RouteTable::parse(path, &source)
+    }
+
+    // Read the rules file when it exists, otherwise fall back to the bundled rules
+    pub fn load(path: &str) -> Result<RouteTable, RoutingError> {
+        if Path::new(path).exists() {
+            RouteTable::from_file(path)
+        } else {
+            println!("Routing rules {} not found, using the bundled rules", path);
+            Ok(RouteTable::default())
+        }
+    }
+
+    // The first rule matching the parcel
+    pub fn route(&self, parcel: &Parcel) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.condition.matches(parcel))
+    }
+}
+
+// Where parsing stopped and why, from the innermost context that was given a message
+fn describe<'a>(e: &VerboseError<&'a str>) -> (&'a str, String) {
+    let described = e.errors.iter().find_map(|(rest, kind)| match kind {
+        VerboseErrorKind::Context(message) => Some((*rest, message.to_string())),
+        _ => None,
+    });
+    described.unwrap_or_else(|| {
+        let rest = e.errors.first().map_or("", |(rest, _)| *rest);
+        match rest.split_whitespace().next() {
+            Some(token) => (rest, format!("unexpected `{}`", token)),
+            None => (rest, "unexpected end of rule".to_string()),
+        }
+    })
+}
+
+// A word that is not the start of a longer word
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> ParseResult<'a, &'a str> {
+    terminated(tag(word), not(peek(satisfy(|c| c.is_alphanumeric() || c == '_'))))
+}
+
+// A state or warehouse name inside a list, which may contain spaces
+fn name(input: &str) -> ParseResult<'_, String> {
+    map(take_while1(|c: char| c.is_alphanumeric() || " -'.".contains(c)), |name: &str| name.trim().to_string())(input)
+}
+
+// A state name, checked against the states addresses are validated against
+fn state_name(input: &str) -> ParseResult<'_, String> {
+    cut(context("expected a Malaysian state or federal territory", verify(name, |name: &str| address::is_state(name))))(input)
+}
+
+fn name_list<'a>(element: fn(&'a str) -> ParseResult<'a, String>) -> impl FnMut(&'a str) -> ParseResult<'a, Vec<String>> {
+    preceded(
+        context("expected `[`", terminated(char('['), space0)),
+        cut(terminated(
+            context("expected a name", separated_list1(delimited(space0, char(','), space0), element)),
+            context("expected `,` or `]`", preceded(space0, char(']'))),
+        )),
+    )
+}
+
+fn text_test(input: &str) -> ParseResult<'_, Condition> {
+    let (input, field) = alt((value(TextField::State, keyword("state")), value(TextField::Warehouse, keyword("warehouse"))))(input)?;
+    let element = match field {
+        TextField::State => state_name,
+        TextField::Warehouse => name,
+    };
+    let test = preceded(tuple((space1, context("expected `in`", keyword("in")), space0)), name_list(element));
+    map(cut(test), move |names| Condition::In(field, names))(input)
+}
+
+fn comparison(input: &str) -> ParseResult<'_, Comparison> {
+    alt((
+        value(Comparison::LessOrEqual, tag("<=")),
+        value(Comparison::GreaterOrEqual, tag(">=")),
+        value(Comparison::NotEqual, tag("!=")),
+        value(Comparison::Less, tag("<")),
+        value(Comparison::Greater, tag(">")),
+        value(Comparison::Equal, tag("=")),
+    ))(input)
+}
+
+fn number_test(input: &str) -> ParseResult<'_, Condition> {
+    let field = alt((
+        value(NumberField::Weight, keyword("weight")),
+        value(NumberField::Quantity, keyword("quantity")),
+        value(NumberField::Total, keyword("total")),
+    ));
+    let test = pair(
+        delimited(space0, context("expected a comparison such as `>` or `<=`", comparison), space0),
+        context("expected a finite number", verify(double, |limit: &f64| limit.is_finite())),
+    );
+    map(pair(field, cut(test)), |(field, (comparison, limit))| Condition::Compare(field, comparison, limit))(input)
+}
+
+fn atom(input: &str) -> ParseResult<'_, Condition> {
+    context(
+        "expected a condition such as `state in [...]`, `weight > 5` or `otherwise`",
+        alt((
+            value(Condition::Always, keyword("otherwise")),
+            map(preceded(pair(keyword("not"), space1), cut(atom)), |condition| Condition::Not(Box::new(condition))),
+            preceded(
+                pair(char('('), space0),
+                cut(terminated(condition, context("expected `)`", preceded(space0, char(')'))))),
+            ),
+            text_test,
+            number_test,
+        )),
+    )(input)
+}
+
+fn and_condition(input: &str) -> ParseResult<'_, Condition> {
+    let (input, first) = atom(input)?;
+    let (input, rest) = many0(preceded(delimited(space1, keyword("and"), space1), cut(atom)))(input)?;
+    Ok((input, if rest.is_empty() { first } else { Condition::And([vec![first], rest].concat()) }))
+}
+
+fn condition(input: &str) -> ParseResult<'_, Condition> {
+    let (input, first) = and_condition(input)?;
+    let (input, rest) = many0(preceded(delimited(space1, keyword("or"), space1), cut(and_condition)))(input)?;
+    Ok((input, if rest.is_empty() { first } else { Condition::Or([vec![first], rest].concat()) }))
+}
+
+fn mode(input: &str) -> ParseResult<'_, TransportMode> {
+    alt((
+        value(TransportMode::Land, keyword("Land")),
+        value(TransportMode::Sea, keyword("Sea")),
+        value(TransportMode::Air, keyword("Air")),
+    ))(input)
+}
+
+fn rule(input: &str) -> ParseResult<'_, (Condition, TransportMode)> {
+    delimited(
+        space0,
+        pair(
+            condition,
+            preceded(
+                delimited(space0, context("expected `=>`", tag("=>")), space0),
+                cut(context("expected a transport mode: Land, Sea or Air", mode)),
+            ),
+        ),
+        space0,
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Line, column and message of the error parsing `source`
+    fn syntax_error(source: &str) -> (usize, usize, String) {
+        match RouteTable::parse("test.rules", source) {
+            Err(RoutingError::Syntax { line, column, message, .. }) => (line, column, message),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_rules_skipping_comments_and_blank_lines() {
+        let source = "# comment\n\nstate in [Sabah, kuala lumpur] and weight <= 2.5 => Air  # inline\notherwise => Land\n";
+        let table = RouteTable::parse("test.rules", source).unwrap();
+        assert_eq!(table.rules.len(), 2);
+        assert_eq!(table.rules[0].line, 3);
+        assert_eq!(
+            table.rules[0].condition,
+            Condition::And(vec![
+                Condition::In(TextField::State, vec!["Sabah".to_string(), "kuala lumpur".to_string()]),
+                Condition::Compare(NumberField::Weight, Comparison::LessOrEqual, 2.5),
+            ])
+        );
+        assert_eq!(table.rules[1], Rule { line: 4, condition: Condition::Always, mode: TransportMode::Land });
+    }
+
+    #[test]
+    fn bundled_rules_parse() {
+        assert!(!RouteTable::default().rules.is_empty());
+    }
+
+    #[test]
+    fn reports_where_a_rule_is_incomplete() {
+        let (line, column, message) = syntax_error("otherwise => Land\nweight > 5 Sea");
+        assert_eq!((line, column), (2, 12));
+        assert_eq!(message, "expected `=>`");
+
+        let (_, column, message) = syntax_error("state in [Sabah => Sea");
+        assert_eq!(column, 17);
+        assert_eq!(message, "expected `,` or `]`");
+    }
+
+    #[test]
+    fn rejects_non_finite_numbers() {
+        for limit in ["nan", "inf", "-infinity"] {
+            let (line, column, message) = syntax_error(&format!("weight > {} => Air", limit));
+            assert_eq!((line, column), (1, 10), "{}", limit);
+            assert_eq!(message, "expected a finite number");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_states() {
+        let (line, column, message) = syntax_error("# East\nstate in [Sabah, Sabha] => Sea");
+        assert_eq!((line, column), (2, 18));
+        assert_eq!(message, "expected a Malaysian state or federal territory");
+
+        // Warehouses come from the config, so any name is accepted
+        assert!(RouteTable::parse("test.rules", "warehouse in [Penang Hub] => Land").is_ok());
+    }
+}
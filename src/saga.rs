@@ -0,0 +1,301 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::config;
+use crate::dead_letter::dead_letter;
+use crate::error::{Error, Result};
+use crate::functions::{send_control, send_envelope, send_queue};
+use crate::structs::{Control, Envelope, Order, OrderStatus};
+use crate::transport::MessageTransport;
+
+// The steps of an order saga, in the order they run. Ship completes once the order is
+// booked on a dispatch run and Deliver once the courier has handed it over.
+pub const ORDER_SAGA: [SagaStep; 4] = [SagaStep::ChargePayment, SagaStep::ReserveStock, SagaStep::Ship, SagaStep::Deliver];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SagaStep {
+    ChargePayment,
+    ReserveStock,
+    Ship,
+    Deliver,
+}
+
+// Undoes a completed step when a later step fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compensation {
+    Refund,
+    ReleaseStock,
+    RecallShipment,
+}
+
+impl SagaStep {
+    // The last step has nothing left after it to fail, so nothing to undo
+    pub fn compensation(self) -> Option<Compensation> {
+        match self {
+            SagaStep::ChargePayment => Some(Compensation::Refund),
+            SagaStep::ReserveStock => Some(Compensation::ReleaseStock),
+            SagaStep::Ship => Some(Compensation::RecallShipment),
+            SagaStep::Deliver => None,
+        }
+    }
+}
+
+impl fmt::Display for SagaStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// Progress reported by the services to the coordinator on the saga queue, carrying
+// the order as it was at that point
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SagaEvent {
+    pub order: Order,
+    pub kind: SagaEventKind,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SagaEventKind {
+    Started,
+    StepCompleted(SagaStep),
+    // The step failed for good, retries included
+    StepFailed(SagaStep, String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SagaConfig {
+    // Sagas still running after this long are compensated. Keep it above the retry
+    // policies' max_age_ms, or orders still being retried get compensated under them.
+    pub timeout_ms: u64,
+}
+
+impl Default for SagaConfig {
+    fn default() -> Self {
+        SagaConfig { timeout_ms: 120_000 }
+    }
+}
+
+pub fn report(transport: &dyn MessageTransport, order: &Order, kind: SagaEventKind) -> Result<()> {
+    let event = SagaEvent { order: order.clone(), kind };
+    send_envelope(transport, &Envelope::Message(&event), "saga")
+}
+
+#[derive(Debug)]
+struct Saga {
+    order: Order,
+    // Steps completed, in the order they completed; each step appears once
+    completed: Vec<SagaStep>,
+    started: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct SagaCoordinator {
+    sagas: HashMap<i32, Saga>,
+    timeout: Duration,
+    // Orders compensated after timing out, which may still be out for delivery
+    expired: HashSet<i32>,
+    pub completed: usize,
+    pub compensated: usize,
+    pub timed_out: usize,
+    // Orders delivered after their saga timed out and was compensated
+    pub conflicts: Vec<i32>,
+}
+
+impl SagaCoordinator {
+    pub fn new(timeout: Duration) -> Self {
+        SagaCoordinator { timeout, ..Default::default() }
+    }
+
+    pub fn in_progress(&self) -> usize {
+        self.sagas.len()
+    }
+
+    pub fn handle(&mut self, transport: &dyn MessageTransport, event: SagaEvent) -> Result<()> {
+        let order_id = event.order.id;
+        if let SagaEventKind::Started = event.kind {
+            println!("[Order ID {}] Saga started", order_id);
+            let saga = Saga { order: event.order, completed: Vec::new(), started: Instant::now() };
+            self.sagas.insert(order_id, saga);
+            return Ok(());
+        }
+
+        let Some(saga) = self.sagas.get_mut(&order_id) else {
+            if matches!(event.kind, SagaEventKind::StepCompleted(SagaStep::Deliver)) && self.expired.contains(&order_id) {
+                return self.flag_conflict(transport, &event);
+            }
+            // Already finished, e.g. compensated after a timeout
+            println!("[Order ID {}] Ignoring late saga event {:?}", order_id, event.kind);
+            return Ok(());
+        };
+        saga.order = event.order;
+
+        match event.kind {
+            SagaEventKind::Started => unreachable!(),
+            SagaEventKind::StepCompleted(step) => {
+                if !saga.completed.contains(&step) {
+                    saga.completed.push(step);
+                }
+                println!("[Order ID {}] Saga step {} completed", order_id, step);
+                if !ORDER_SAGA.iter().all(|step| saga.completed.contains(step)) {
+                    return Ok(());
+                }
+                println!("[Order ID {}] Saga completed", order_id);
+                self.completed += 1;
+                // Delivered, so the reserved stock can be taken off the shelf
+                if let Some(saga) = self.sagas.remove(&order_id) {
+                    send_queue(transport, &saga.order, "commit_inventory")?;
+                }
+                Ok(())
+            }
+            SagaEventKind::StepFailed(step, reason) => {
+                println!("[Order ID {}] Saga step {} failed: {}", order_id, step, reason);
+                self.compensated += 1;
+                self.compensate(transport, order_id, format!("{} failed: {}", step, reason))
+            }
+        }
+    }
+
+    // Compensate every saga that has been running longer than the timeout
+    pub fn expire(&mut self, transport: &dyn MessageTransport) -> Result<()> {
+        let expired: Vec<i32> = self
+            .sagas
+            .iter()
+            .filter(|(_, saga)| saga.started.elapsed() > self.timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for order_id in expired {
+            println!("[Order ID {}] Saga timed out after {}ms", order_id, self.timeout.as_millis());
+            self.timed_out += 1;
+            self.expired.insert(order_id);
+            let reason = format!("saga timed out after {}ms", self.timeout.as_millis());
+            self.compensate(transport, order_id, reason)?;
+        }
+        Ok(())
+    }
+
+    // The order was delivered after its saga timed out, so it has been cancelled,
+    // refunded and its stock released, yet the customer has it and the database records
+    // it as both Cancelled and Delivered. Park the event on the saga dead-letter queue
+    // for someone to reconcile by hand.
+    fn flag_conflict(&mut self, transport: &dyn MessageTransport, event: &SagaEvent) -> Result<()> {
+        let order_id = event.order.id;
+        println!("[Order ID {}] Conflict: delivered after its saga timed out and was compensated", order_id);
+        self.conflicts.push(order_id);
+        let queue = config::get().queues.resolve("saga").ok_or_else(|| Error::UnknownQueue("saga".to_string()))?;
+        let payload = serde_json::to_string(&Envelope::Message(event))?;
+        let reason = "delivered after the saga timed out; recorded as both Cancelled and Delivered and refunded";
+        dead_letter(transport, &queue, &payload, reason)
+    }
+
+    // Cancel the order and undo its completed steps, last step first. The refund is
+    // always the last compensation and records the order once done; without a
+    // payment to refund, the order goes to the database system directly.
+    fn compensate(&mut self, transport: &dyn MessageTransport, order_id: i32, reason: String) -> Result<()> {
+        let Some(Saga { mut order, completed, .. }) = self.sagas.remove(&order_id) else {
+            return Ok(());
+        };
+        if let Err(e) = order.transition(OrderStatus::Cancelled) {
+            // Too late to cancel, e.g. the order was delivered after the saga timed out
+            println!("[Order ID {}] Cannot compensate: {}", order.id, e);
+            return Ok(());
+        }
+        order.cancel_reason = Some(reason);
+
+        for step in completed.iter().rev() {
+            match step.compensation() {
+                // Stop the delivery in progress and any redelivery the monitoring system
+                // has yet to make
+                Some(Compensation::RecallShipment) => {
+                    println!("[Order ID {}] Recalling shipment to {}", order.id, order.shipping_address.locality());
+                    send_control(transport, Control::Recall(order.id), "delivery")?;
+                    send_control(transport, Control::Recall(order.id), "monitoring")?;
+                }
+                Some(Compensation::ReleaseStock) => {
+                    println!("[Order ID {}] Releasing reserved stock......", order.id);
+                    send_queue(transport, &order, "return_inventory")?;
+                }
+                Some(Compensation::Refund) => {
+                    println!("[Order ID {}] Send to payment system for refund...", order.id);
+                    send_queue(transport, &order, "refund")?;
+                }
+                None => {}
+            }
+        }
+        if !completed.contains(&SagaStep::ChargePayment) {
+            send_queue(transport, &order, "database")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::transport::InMemoryTransport;
+
+    fn event(kind: SagaEventKind) -> SagaEvent {
+        let address = Address::new("Nur Aisyah", &["12 Jalan Ampang"], "50450", "Kuala Lumpur", "Kuala Lumpur");
+        SagaEvent { order: Order::new(1, Vec::new(), address), kind }
+    }
+
+    #[test]
+    fn completes_only_once_every_step_has() {
+        let transport = InMemoryTransport::new();
+        let mut coordinator = SagaCoordinator::new(Duration::from_secs(60));
+        coordinator.handle(&transport, event(SagaEventKind::Started)).unwrap();
+        for step in [SagaStep::ChargePayment, SagaStep::ChargePayment, SagaStep::ReserveStock] {
+            coordinator.handle(&transport, event(SagaEventKind::StepCompleted(step))).unwrap();
+        }
+        assert_eq!((coordinator.completed, coordinator.in_progress()), (0, 1));
+
+        coordinator.handle(&transport, event(SagaEventKind::StepCompleted(SagaStep::Ship))).unwrap();
+        assert_eq!((coordinator.completed, coordinator.in_progress()), (0, 1));
+
+        coordinator.handle(&transport, event(SagaEventKind::StepCompleted(SagaStep::Deliver))).unwrap();
+        assert_eq!((coordinator.completed, coordinator.in_progress()), (1, 0));
+    }
+
+    #[test]
+    fn flags_delivery_after_a_timeout() {
+        let transport = InMemoryTransport::new();
+        let mut coordinator = SagaCoordinator::new(Duration::ZERO);
+        coordinator.handle(&transport, event(SagaEventKind::Started)).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        coordinator.expire(&transport).unwrap();
+        assert_eq!(coordinator.timed_out, 1);
+
+        coordinator.handle(&transport, event(SagaEventKind::StepCompleted(SagaStep::Deliver))).unwrap();
+        assert_eq!(coordinator.conflicts, vec![1]);
+        let dlq = config::get().queues.dead_letter(&config::get().queues.resolve("saga").unwrap());
+        assert_eq!(transport.len(&dlq), 1);
+    }
+
+    #[test]
+    fn recalls_a_shipped_order_when_delivery_fails() {
+        let transport = InMemoryTransport::new();
+        let mut coordinator = SagaCoordinator::new(Duration::from_secs(60));
+        coordinator.handle(&transport, event(SagaEventKind::Started)).unwrap();
+        for step in [SagaStep::ChargePayment, SagaStep::ReserveStock, SagaStep::Ship] {
+            coordinator.handle(&transport, event(SagaEventKind::StepCompleted(step))).unwrap();
+        }
+        let failed = SagaEventKind::StepFailed(SagaStep::Deliver, "courier gave up".to_string());
+        coordinator.handle(&transport, event(failed)).unwrap();
+        assert_eq!((coordinator.compensated, coordinator.in_progress()), (1, 0));
+
+        let queue = |name: &str| config::get().queues.resolve(name).unwrap();
+        for name in ["delivery", "monitoring"] {
+            let message = transport.try_recv(&queue(name)).unwrap().unwrap();
+            let envelope: Envelope<Order> = serde_json::from_str(&message).unwrap();
+            assert!(matches!(envelope, Envelope::Control(Control::Recall(1))));
+        }
+        for name in ["return_inventory", "refund"] {
+            let message = transport.try_recv(&queue(name)).unwrap().unwrap();
+            let envelope: Envelope<Order> = serde_json::from_str(&message).unwrap();
+            assert!(matches!(envelope, Envelope::Message(order) if order.status == OrderStatus::Cancelled));
+        }
+        assert_eq!(transport.len(&queue("database")), 0);
+    }
+}
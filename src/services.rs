@@ -0,0 +1,836 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::backorder::BackorderStore;
+use crate::catalog;
+use crate::config;
+use crate::dead_letter::dead_letter;
+use crate::dispatch::DispatchBoard;
+use crate::error::{Error, Result};
+use crate::functions::{
+    abandon_order,
+    generate_orders,
+    commit_stock,
+    expire_backorders,
+    expire_reservations,
+    handle_return,
+    inventory_checking,
+    process_delivery,
+    process_payment,
+    process_refund,
+    receive_orders,
+    receive_stock,
+    release_backorders,
+    send_control,
+    send_envelope,
+    redelivery,
+    repayment,
+    send_queue,
+    Inbox,
+    Tagged,
+};
+use crate::money::{Currency, Money};
+use crate::payment::PaymentGateway;
+use crate::pricing::price_order;
+use crate::retry::{RetryConfig, RetryDecision, RetrySchedule};
+use crate::routing::RouteTable;
+use crate::saga::{report, SagaCoordinator, SagaEvent, SagaEventKind};
+use crate::structs::{Control, Envelope, Order, OrderStatus, RefundStatus};
+use crate::tracking::{TrackingEvent, TrackingLog, TrackingQuery, TrackingReply};
+use crate::transport::MessageTransport;
+use crate::warehouse::Warehouses;
+
+// Each service loop takes the transport it talks through, so the same code runs
+// against the broker (the binaries in src/bin) or in-process queues.
+
+// Broker queue name for a logical queue, as set in the config
+fn broker_queue(name: &str) -> Result<String> {
+    config::get().queues.resolve(name).ok_or_else(|| Error::UnknownQueue(name.to_string()))
+}
+
+// Spawn a thread receiving from the logical queue into an in-process channel.
+// Only Shutdown, Drain and Recall control messages reach the returned receiver; if the
+// receiving thread fails, the channel is closed and the service loop stops.
+fn spawn_receiver(transport: &Arc<dyn MessageTransport>, name: &str) -> Result<Inbox> {
+    let (order_tx, order_rx): (Sender<Tagged>, Receiver<Tagged>) = mpsc::channel();
+    let queue_name = broker_queue(name)?;
+    let producers = config::get().producers(name);
+    let inbox = Inbox::new(Arc::clone(transport), &queue_name, order_rx);
+    let transport = Arc::clone(transport);
+    thread::spawn(move || {
+        if let Err(e) = receive_orders(transport, &queue_name, producers, order_tx) {
+            println!("[{}] Receiving stopped: {}", queue_name, e);
+        }
+    });
+    Ok(inbox)
+}
+
+// Errors about a single order send the message as it was received to the dead-letter
+// queue of the queue it came from, so the service keeps running and a requeued letter
+// is processed afresh; anything else (broker down, channel closed) stops the service.
+fn recover(transport: &dyn MessageTransport, queue: &str, order: &Order, received: &str, e: Error) -> Result<()> {
+    if !e.is_per_message() {
+        println!("[Order ID {}] Stopping on {} queue: {}", order.id, queue, e);
+        return Err(e);
+    }
+    println!("[Order ID {}] Could not process order from {} queue: {}", order.id, queue, e);
+    dead_letter(transport, &broker_queue(queue)?, received, &e.to_string())
+}
+
+// Order system
+
+// Orders refused at intake never enter the saga; they go straight to the database so
+// the summary accounts for every order generated
+fn reject(transport: &dyn MessageTransport, order: &mut Order, reason: String) -> Result<()> {
+    println!("[Order ID {}] Rejected: {}", order.id, reason);
+    order.transition(OrderStatus::Rejected)?;
+    order.cancel_reason = Some(reason);
+    send_queue(transport, order, "database")
+}
+
+// Generates orders `next_id` to `limit`, moving `next_id` past each order once it is
+// handed on, so a restarted order system carries on without repeating order ids
+pub fn run_order(transport: Arc<dyn MessageTransport>, next_id: &mut i32, limit: i32, interval: Duration) -> Result<()> {
+    let (order_tx, order_rx) = mpsc::channel();
+
+    // Order generation thread
+    let order_ids = *next_id..=limit;
+    thread::spawn(move || {
+        generate_orders(&order_tx, order_ids, interval);
+    });
+
+    // Order processing in the main thread
+    while let Ok(mut order) = order_rx.recv() {
+        println!("Order ID: {}", order.id);
+        if let Err(reason) = order.validate_lines() {
+            reject(transport.as_ref(), &mut order, reason)?;
+            *next_id = order.id + 1;
+            continue;
+        }
+        match order.shipping_address.validate() {
+            Ok(state) => order.shipping_address.state = state.to_string(),
+            Err(e) => {
+                reject(transport.as_ref(), &mut order, format!("invalid shipping address: {}", e))?;
+                *next_id = order.id + 1;
+                continue;
+            }
+        }
+        if let Err(e) = price_order(&mut order, catalog::get(), &config::get().pricing) {
+            reject(transport.as_ref(), &mut order, e.to_string())?;
+            *next_id = order.id + 1;
+            continue;
+        }
+        for line in &order.lines {
+            match catalog::get().find(&line.sku) {
+                Some(product) => println!(
+                    "Item: {} ({}), Quantity: {}, Unit price: {}, SST: {}",
+                    product.name, line.sku, line.quantity, line.unit_price, line.tax
+                ),
+                None => println!("Item: {}, Quantity: {}", line.sku, line.quantity),
+            }
+        }
+        println!("Subtotal: {}, SST: {}, Shipping: {}", order.totals.subtotal, order.totals.tax, order.totals.shipping);
+        println!("Total: {}", order.totals.total);
+        println!("Shipping Address: {}", order.shipping_address);
+        println!("Service Level: {}", order.service_level);
+        println!("Status: {}", order.status);
+        report(transport.as_ref(), &order, SagaEventKind::Started)?;
+        if let Err(e) = send_queue(transport.as_ref(), &order, "payment") {
+            let payload = serde_json::to_string(&Envelope::Message(&order))?;
+            recover(transport.as_ref(), "payment", &order, &payload, e)?;
+        }
+        *next_id = order.id + 1;
+        println!("------------------------------------------------------------------");
+    }
+
+    // The generator has finished and dropped its sender. The saga coordinator passes
+    // the shutdown on to the payment system once every order has finished.
+    send_control(transport.as_ref(), Control::Shutdown, "saga")?;
+    println!("All orders have been processed. Shutting the down order system...");
+    Ok(())
+}
+
+// Payment system. Refunds keep arriving long after the last payment, so they are a
+// service of their own, run next to the payment service on the same gateway.
+pub fn run_refunds(transport: Arc<dyn MessageTransport>, gateway: Arc<dyn PaymentGateway>) -> Result<()> {
+    let refund_rx = spawn_receiver(&transport, "refund")?;
+    loop {
+        match refund_rx.recv() {
+            Ok((Envelope::Message(mut order), received)) => {
+                if let Err(e) = process_refund(transport.as_ref(), gateway.as_ref(), &mut order) {
+                    recover(transport.as_ref(), "refund", &order, &received, e)?;
+                }
+            }
+            Ok((Envelope::Control(_), _)) => {
+                // Refunds come from the end of the pipeline, so their shutdown closes the database
+                send_control(transport.as_ref(), Control::Shutdown, "database")?;
+                println!("Refund processing has been shut down.");
+                return Ok(());
+            }
+            Err(_) => return Err(Error::Disconnected("refund".to_string())),
+        }
+    }
+}
+
+pub fn run_payment(transport: Arc<dyn MessageTransport>, gateway: Arc<dyn PaymentGateway>) -> Result<()> {
+    // Spawn a thread to receive orders
+    let order_rx = spawn_receiver(&transport, "payment")?;
+
+    // Main thread loop for processing orders
+    loop {
+        // Process orders sequentially
+        match order_rx.recv() {
+            Ok((Envelope::Message(mut order), received)) => {
+                if let Err(e) = process_payment(transport.as_ref(), gateway.as_ref(), &mut order) {
+                    recover(transport.as_ref(), "payment", &order, &received, e)?;
+                }
+            }
+            Ok((Envelope::Control(_), _)) => {
+                // Shutdown or drain: pass the shutdown on to the next stage
+                send_control(transport.as_ref(), Control::Shutdown, "inventory")?;
+                println!("Shutting down the payment system...");
+                return Ok(());
+            }
+            Err(_) => return Err(Error::Disconnected("payment".to_string())),
+        }
+    }
+}
+
+// Inventory system
+pub fn run_inventory(transport: Arc<dyn MessageTransport>) -> Result<()> {
+    // Initialize the warehouses
+    let settings = &config::get().inventory;
+    let inventory = Arc::new(Mutex::new(Warehouses::from_config(settings)?));
+    let mut backorders = BackorderStore::new(Duration::from_millis(settings.backorder_max_wait_ms));
+
+    // Spawn threads to receive orders, returns and commits
+    let order_rx = spawn_receiver(&transport, "inventory")?;
+    let return_rx = spawn_receiver(&transport, "return_inventory")?;
+    let commit_rx = spawn_receiver(&transport, "commit_inventory")?;
+
+    // Main thread loop for processing orders, returns and commits
+    loop {
+        expire_reservations(&inventory)?;
+        receive_stock(&inventory)?;
+        release_backorders(transport.as_ref(), &inventory, settings.fulfilment, &mut backorders)?;
+        expire_backorders(transport.as_ref(), &mut backorders)?;
+
+        // Commits of delivered orders first, they were sent long before the shutdown
+        while let Ok((Envelope::Message(order), received)) = commit_rx.try_recv() {
+            if let Err(e) = commit_stock(&inventory, &order) {
+                recover(transport.as_ref(), "commit_inventory", &order, &received, e)?;
+            }
+        }
+
+        // Then returns
+        match return_rx.try_recv() {
+            Ok((Envelope::Message(return_order), received)) => {
+                println!("[Return ID {}] Received return order", return_order.id);
+                if let Err(e) = handle_return(&inventory, &return_order) {
+                    recover(transport.as_ref(), "return_inventory", &return_order, &received, e)?;
+                }
+            }
+            Ok((Envelope::Control(_), _)) => println!("Return processing has been shut down."),
+            Err(_) => {
+                // No returns to process, proceed to handle orders
+                match order_rx.try_recv() {
+                    Ok((Envelope::Message(mut order), received)) => {
+                        // Check the inventory and process the order
+                        if let Err(e) = inventory_checking(transport.as_ref(), &inventory, &mut order, settings, &mut backorders) {
+                            recover(transport.as_ref(), "inventory", &order, &received, e)?;
+                        }
+                    }
+                    Ok((Envelope::Control(_), _)) => {
+                        send_control(transport.as_ref(), Control::Shutdown, "delivery")?;
+                        println!("Inventory system has been shut down.");
+                        return Ok(());
+                    }
+                    Err(TryRecvError::Empty) => {
+                        // No orders to process
+                    }
+                    Err(TryRecvError::Disconnected) => return Err(Error::Disconnected("inventory".to_string())),
+                }
+            }
+        }
+
+        // Add a small sleep to avoid busy waiting
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Delivery system
+pub fn run_delivery(transport: Arc<dyn MessageTransport>) -> Result<()> {
+    let routes_path = config::get().routes_path();
+    let routes = RouteTable::load(routes_path)?;
+    println!("Loaded {} routing rules from {}", routes.rules.len(), routes_path);
+    let mut board = DispatchBoard::new(&config::get().shipping.couriers);
+    // Orders whose saga has been compensated, which must not go out
+    let mut recalled = HashSet::new();
+
+    // Spawn a thread to receive orders
+    let order_rx = spawn_receiver(&transport, "delivery")?;
+
+    // Main thread loop for processing orders
+    loop {
+        // Process orders sequentially
+        match order_rx.recv() {
+            Ok((Envelope::Message(order), _)) if recalled.contains(&order.id) => {
+                println!("[Order ID {}] Shipment was recalled, not delivering", order.id);
+            }
+            Ok((Envelope::Message(mut order), received)) => {
+                if let Err(e) = process_delivery(transport.as_ref(), &routes, &mut board, &mut order) {
+                    recover(transport.as_ref(), "delivery", &order, &received, e)?;
+                }
+            }
+            Ok((Envelope::Control(Control::Recall(order_id)), _)) => {
+                recalled.insert(order_id);
+            }
+            Ok((Envelope::Control(_), _)) => {
+                // Shutdown or drain: pass the shutdown on to the next stage
+                send_control(transport.as_ref(), Control::Shutdown, "monitoring")?;
+                println!("Dispatch runs booked:");
+                for run in board.runs() {
+                    println!("  {}", run);
+                }
+                println!("Shutting down the delivery system...");
+                return Ok(());
+            }
+            Err(_) => return Err(Error::Disconnected("delivery".to_string())),
+        }
+    }
+}
+
+// Monitoring system. A failed order waits in the schedule until its retry is due, so
+// one order backing off does not hold up the others.
+type Scheduled = RetrySchedule<(Order, String)>;
+
+// Schedule the order's next retry, or give up on it
+fn monitor_order(transport: &dyn MessageTransport, retry: &RetryConfig, recalled: &HashSet<i32>, scheduled: &mut Scheduled, order: Order, received: String) -> Result<()> {
+    println!("[Order ID {}] Monitoring system received order", order.id);
+    if recalled.contains(&order.id) {
+        println!("[Order ID {}] Shipment was recalled, not retrying", order.id);
+        return Ok(());
+    }
+
+    let (policy, retries) = match order.status {
+        OrderStatus::PaymentFailed => (&retry.payment, order.payment_retries),
+        OrderStatus::DeliveryFailed => (&retry.delivery, order.delivery_retries),
+        status => {
+            println!("[Order ID {}] Nothing to recover for status {}", order.id, status);
+            return Ok(());
+        }
+    };
+
+    match policy.decide(retries, order.age()) {
+        RetryDecision::Retry(delay) => {
+            println!("[Order ID {}] Retrying in {}ms", order.id, delay.as_millis());
+            scheduled.schedule(Instant::now() + delay, (order, received));
+        }
+        RetryDecision::GiveUp(reason) => {
+            if let Err(e) = abandon_order(transport, &order, &reason) {
+                recover(transport, "monitoring", &order, &received, e)?;
+            }
+            println!("------------------------------------------------------------");
+        }
+    }
+    Ok(())
+}
+
+// Make every retry that has come due. A retry that fails again goes back on the
+// monitoring queue.
+fn retry_due(transport: &dyn MessageTransport, gateway: &dyn PaymentGateway, scheduled: &mut Scheduled) -> Result<()> {
+    while let Some((mut order, received)) = scheduled.pop_due(Instant::now()) {
+        let result = if order.status == OrderStatus::PaymentFailed {
+            repayment(transport, gateway, &mut order)
+        } else {
+            redelivery(transport, &mut order)
+        };
+        if let Err(e) = result {
+            recover(transport, "monitoring", &order, &received, e)?;
+        }
+        println!("------------------------------------------------------------");
+    }
+    Ok(())
+}
+
+fn recall(recalled: &mut HashSet<i32>, scheduled: &mut Scheduled, order_id: i32) {
+    recalled.insert(order_id);
+    let before = scheduled.len();
+    scheduled.retain(|(order, _)| order.id != order_id);
+    if scheduled.len() < before {
+        println!("[Order ID {}] Shipment was recalled, dropped its scheduled retry", order_id);
+    }
+}
+
+pub fn run_monitor(transport: Arc<dyn MessageTransport>, gateway: Arc<dyn PaymentGateway>) -> Result<()> {
+    // Spawn a thread to receive orders
+    let order_rx = spawn_receiver(&transport, "monitoring")?;
+    let retry = &config::get().retry;
+    let mut recalled = HashSet::new();
+    let mut scheduled = Scheduled::default();
+
+    // Main thread loop: make the retries that are due, then wait for the next order
+    // until the next retry is due
+    loop {
+        retry_due(transport.as_ref(), gateway.as_ref(), &mut scheduled)?;
+        let received = match scheduled.next_due() {
+            Some(due) => order_rx.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => order_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok((Envelope::Message(order), received)) => monitor_order(transport.as_ref(), retry, &recalled, &mut scheduled, order, received)?,
+            Ok((Envelope::Control(Control::Recall(order_id)), _)) => recall(&mut recalled, &mut scheduled, order_id),
+            Ok((Envelope::Control(_), _)) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::Disconnected("monitoring".to_string())),
+        }
+    }
+
+    // Retries still scheduled, and failed retries requeued behind the shutdown, are
+    // finished first. Each retry succeeds, is retried again or is cancelled, so this ends.
+    let queue = broker_queue("monitoring")?;
+    loop {
+        retry_due(transport.as_ref(), gateway.as_ref(), &mut scheduled)?;
+        match transport.try_recv(&queue)? {
+            Some(message) => match serde_json::from_str::<Envelope<Order>>(&message) {
+                Ok(Envelope::Message(order)) => monitor_order(transport.as_ref(), retry, &recalled, &mut scheduled, order, message)?,
+                Ok(Envelope::Control(Control::Recall(order_id))) => recall(&mut recalled, &mut scheduled, order_id),
+                Ok(Envelope::Control(control)) => println!("[{}] Ignoring {} after shutdown", queue, control),
+                Err(e) => dead_letter(transport.as_ref(), &queue, &message, &Error::from(e).to_string())?,
+            },
+            None => match scheduled.next_due() {
+                Some(due) => thread::sleep(due.saturating_duration_since(Instant::now())),
+                None => break,
+            },
+        }
+    }
+    send_control(transport.as_ref(), Control::Shutdown, "refund")?;
+    // Delivery has already shut down, so no more tracking events follow
+    send_control(transport.as_ref(), Control::Shutdown, "tracking")?;
+    println!("Shutting down the monitor system...");
+    Ok(())
+}
+
+// Saga coordinator service. The order system's Shutdown is held back until every
+// saga has finished, so no order is still travelling through the pipeline when the
+// services behind it shut down.
+pub fn run_saga(transport: Arc<dyn MessageTransport>) -> Result<()> {
+    let config = config::get();
+    let queue = broker_queue("saga")?;
+    let producers = config.producers("saga");
+    let mut coordinator = SagaCoordinator::new(Duration::from_millis(config.saga.timeout_ms));
+    let mut shutdowns = 0;
+
+    loop {
+        match transport.try_recv(&queue)? {
+            Some(message) => match serde_json::from_str::<Envelope<SagaEvent>>(&message) {
+                Ok(Envelope::Message(event)) => coordinator.handle(transport.as_ref(), event)?,
+                Ok(Envelope::Control(Control::Shutdown)) => {
+                    shutdowns += 1;
+                    println!("[{}] Shutdown received from {}/{} producers", queue, shutdowns, producers);
+                }
+                Ok(Envelope::Control(control)) => println!("[{}] Ignoring {}", queue, control),
+                Err(e) => dead_letter(transport.as_ref(), &queue, &message, &Error::from(e).to_string())?,
+            },
+            None => {
+                coordinator.expire(transport.as_ref())?;
+                if shutdowns >= producers && coordinator.in_progress() == 0 {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    println!(
+        "Sagas: {} completed, {} compensated, {} timed out",
+        coordinator.completed, coordinator.compensated, coordinator.timed_out
+    );
+    if !coordinator.conflicts.is_empty() {
+        println!("Delivered after being cancelled, see the saga dead-letter queue: {:?}", coordinator.conflicts);
+    }
+    send_control(transport.as_ref(), Control::Shutdown, "payment")?;
+    println!("Shutting down the saga coordinator...");
+    Ok(())
+}
+
+// Tracking system: records the tracking history of every shipment in `log`, which
+// outlives the service so it can be queried by order id, and is kept across restarts.
+// While it runs, it answers queries for an order's history on the tracking_query queue.
+pub fn run_tracking(transport: Arc<dyn MessageTransport>, log: &mut TrackingLog) -> Result<()> {
+    let queue = broker_queue("tracking")?;
+    let query_queue = broker_queue("tracking_query")?;
+    let producers = config::get().producers("tracking");
+    let mut shutdowns = 0;
+
+    while shutdowns < producers {
+        let event = transport.try_recv(&queue)?;
+        let query = transport.try_recv(&query_queue)?;
+        if event.is_none() && query.is_none() {
+            thread::sleep(Duration::from_millis(50));
+        }
+        if let Some(message) = event.filter(|message| !message.is_empty()) {
+            match serde_json::from_str::<Envelope<TrackingEvent>>(&message) {
+                Ok(Envelope::Message(event)) => log.record(event),
+                Ok(Envelope::Control(Control::Shutdown)) => {
+                    shutdowns += 1;
+                    println!("[{}] Shutdown received from {}/{} producers", queue, shutdowns, producers);
+                }
+                Ok(Envelope::Control(control)) => println!("[{}] Ignoring {}", queue, control),
+                Err(e) => dead_letter(transport.as_ref(), &queue, &message, &Error::from(e).to_string())?,
+            }
+        }
+        if let Some(message) = query {
+            answer_query(transport.as_ref(), log, &query_queue, &message)?;
+        }
+    }
+
+    println!("Tracked {} shipments. Shutting down the tracking system...", log.len());
+    Ok(())
+}
+
+fn answer_query(transport: &dyn MessageTransport, log: &TrackingLog, queue: &str, message: &str) -> Result<()> {
+    match serde_json::from_str::<Envelope<TrackingQuery>>(message) {
+        Ok(Envelope::Message(query)) => {
+            println!("[Order ID {}] Tracking history requested, {} events", query.order_id, log.history(query.order_id).len());
+            let reply = TrackingReply { order_id: query.order_id, events: log.history(query.order_id).to_vec() };
+            transport.send(serde_json::to_string(&Envelope::Message(&reply))?, &query.reply_to)
+        }
+        Ok(Envelope::Control(control)) => {
+            println!("[{}] Ignoring {}", queue, control);
+            Ok(())
+        }
+        Err(e) => dead_letter(transport, queue, message, &Error::from(e).to_string()),
+    }
+}
+
+// Ask the running tracking system for an order's tracking history, waiting up to
+// `timeout` for the answer. Answers come back on a queue of this process's own.
+pub fn query_tracking(transport: &dyn MessageTransport, order_id: i32, timeout: Duration) -> Result<Vec<TrackingEvent>> {
+    let reply_to = format!("{}.reply.{}", broker_queue("tracking_query")?, std::process::id());
+    let query = TrackingQuery { order_id, reply_to: reply_to.clone() };
+    send_envelope(transport, &Envelope::Message(&query), "tracking_query")?;
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match transport.try_recv(&reply_to)? {
+            Some(message) => match serde_json::from_str::<Envelope<TrackingReply>>(&message)? {
+                Envelope::Message(reply) if reply.order_id == order_id => return Ok(reply.events),
+                // A late answer to an earlier query
+                _ => {}
+            },
+            None => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+    Err(Error::Transport(format!("the tracking system did not answer within {}ms", timeout.as_millis())))
+}
+
+// Final statuses recorded by the database system
+#[derive(Debug)]
+pub struct Summary {
+    pub recorded: usize,
+    pub by_status: BTreeMap<OrderStatus, usize>,
+    pub refunded: usize,
+    pub failed_refunds: usize,
+    // Totals of delivered orders, and of orders whose payment was refunded
+    pub sales: Money,
+    pub refunded_amount: Money,
+    // What couriers charged for the orders shipped
+    pub shipping_costs: Money,
+}
+
+impl Summary {
+    pub fn new(currency: Currency) -> Self {
+        Summary {
+            recorded: 0,
+            by_status: BTreeMap::new(),
+            refunded: 0,
+            failed_refunds: 0,
+            sales: Money::zero(currency),
+            refunded_amount: Money::zero(currency),
+            shipping_costs: Money::zero(currency),
+        }
+    }
+
+    // Count the order in. The amounts are added up before anything is counted, so an
+    // order in another currency is refused and leaves the summary as it was.
+    pub fn record(&mut self, order: &Order) -> Result<()> {
+        let sales = match order.status {
+            OrderStatus::Delivered => self.sales.checked_add(order.totals.total)?,
+            _ => self.sales,
+        };
+        let shipping_costs = match &order.shipment {
+            Some(shipment) => self.shipping_costs.checked_add(shipment.cost)?,
+            None => self.shipping_costs,
+        };
+        let refunded_amount = match order.refund {
+            Some(RefundStatus::Refunded) => self.refunded_amount.checked_add(order.totals.total)?,
+            _ => self.refunded_amount,
+        };
+
+        self.recorded += 1;
+        *self.by_status.entry(order.status).or_insert(0) += 1;
+        (self.sales, self.shipping_costs, self.refunded_amount) = (sales, shipping_costs, refunded_amount);
+        match order.refund {
+            Some(RefundStatus::Refunded) => self.refunded += 1,
+            Some(RefundStatus::Failed(_)) => self.failed_refunds += 1,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Orders recorded: {}", self.recorded)?;
+        for (status, count) in &self.by_status {
+            writeln!(f, "  {:<15} {}", status.to_string(), count)?;
+        }
+        writeln!(f, "Sales: {}", self.sales)?;
+        writeln!(f, "Shipping costs: {}", self.shipping_costs)?;
+        writeln!(f, "Refunds: {} refunded ({}), {} failed", self.refunded, self.refunded_amount, self.failed_refunds)
+    }
+}
+
+// Database system. The summary is kept by the caller, so a restarted database system
+// keeps counting from where the last one stopped.
+pub fn run_database(transport: Arc<dyn MessageTransport>, summary: &mut Summary) -> Result<()> {
+    // Spawn a thread to receive orders
+    let order_rx = spawn_receiver(&transport, "database")?;
+
+    loop {
+        match order_rx.recv() {
+            Ok((Envelope::Message(order), received)) => {
+                if let Err(e) = summary.record(&order) {
+                    recover(transport.as_ref(), "database", &order, &received, e)?;
+                    continue;
+                }
+                println!(
+                    "[Order ID {}] Items: {}, Shipping Address: {}, Final Status: {}",
+                    order.id, order.describe_lines(), order.shipping_address, order.status
+                );
+                println!("[Order ID {}] Totals: {}", order.id, order.totals);
+                if !order.fulfilment.is_empty() {
+                    let shipped_from: Vec<String> = order.fulfilment.iter().map(|a| a.to_string()).collect();
+                    println!("[Order ID {}] Shipped from: {}", order.id, shipped_from.join(", "));
+                }
+                if let Some(shipment) = &order.shipment {
+                    println!("[Order ID {}] Shipment: {}", order.id, shipment);
+                }
+                if let Some(reason) = &order.cancel_reason {
+                    println!("[Order ID {}] {}: {}", order.id, order.status, reason);
+                }
+                if let Some(refund) = &order.refund {
+                    println!("[Order ID {}] Refund: {}", order.id, refund);
+                }
+            }
+            Ok((Envelope::Control(_), _)) => {
+                println!("Shutting down the database system...");
+                return Ok(());
+            }
+            Err(_) => return Err(Error::Disconnected("database".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use crate::address::Address;
+    use crate::payment::{PaymentOutcome, ScriptedGateway};
+    use crate::tracking::TrackingStage;
+    use crate::transport::InMemoryTransport;
+
+    const ORDERS: i32 = 6;
+
+    type PaymentService = fn(Arc<dyn MessageTransport>, Arc<dyn PaymentGateway>) -> Result<()>;
+
+    // Run every service over in-process queues, consumers first as the orchestrator
+    // does, and return what the database recorded
+    fn run_pipeline(gateway: Arc<dyn PaymentGateway>) -> Summary {
+        let transport: Arc<dyn MessageTransport> = Arc::new(InMemoryTransport::new());
+        let spawn = |run: fn(Arc<dyn MessageTransport>) -> Result<()>| {
+            let transport = Arc::clone(&transport);
+            thread::spawn(move || run(transport))
+        };
+        let with_gateway = |run: PaymentService| {
+            let (transport, gateway) = (Arc::clone(&transport), Arc::clone(&gateway));
+            thread::spawn(move || run(transport, gateway))
+        };
+
+        let database = {
+            let transport = Arc::clone(&transport);
+            thread::spawn(move || {
+                let mut summary = Summary::new(config::get().pricing.currency);
+                run_database(transport, &mut summary).map(|_| summary)
+            })
+        };
+        let tracking = {
+            let transport = Arc::clone(&transport);
+            thread::spawn(move || run_tracking(transport, &mut TrackingLog::default()))
+        };
+        let services = [
+            with_gateway(run_refunds),
+            with_gateway(run_monitor),
+            spawn(run_delivery),
+            spawn(run_inventory),
+            with_gateway(run_payment),
+            spawn(run_saga),
+        ];
+        run_order(Arc::clone(&transport), &mut 1, ORDERS, Duration::from_millis(10)).unwrap();
+
+        for service in services {
+            service.join().unwrap().unwrap();
+        }
+        tracking.join().unwrap().unwrap();
+        database.join().unwrap().unwrap()
+    }
+
+    #[test]
+    fn every_order_reaches_a_final_status() {
+        // Enough authorisations for every charge, retries included
+        let gateway = Arc::new(ScriptedGateway::new(vec![PaymentOutcome::Authorised; 4 * ORDERS as usize]));
+        let summary = run_pipeline(gateway);
+
+        // Rejected orders are recorded too, so every order generated is accounted for
+        assert_eq!(summary.recorded, ORDERS as usize);
+        assert_eq!(summary.by_status.values().sum::<usize>(), summary.recorded);
+        for status in summary.by_status.keys() {
+            assert!(matches!(status, OrderStatus::Delivered | OrderStatus::Cancelled), "order left in {}", status);
+        }
+        assert_eq!(summary.refunded, summary.by_status.get(&OrderStatus::Cancelled).copied().unwrap_or(0));
+        assert_eq!(summary.failed_refunds, 0);
+    }
+
+    // In-process queues whose first send to the payment queue fails, as if the broker
+    // connection dropped
+    struct FailingOnce {
+        queues: InMemoryTransport,
+        failed: AtomicBool,
+    }
+
+    impl MessageTransport for FailingOnce {
+        fn send(&self, msg: String, queue_name: &str) -> Result<()> {
+            if queue_name == broker_queue("payment")? && !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(Error::Disconnected(queue_name.to_string()));
+            }
+            self.queues.send(msg, queue_name)
+        }
+
+        fn recv(&self, queue_name: &str) -> Result<String> {
+            self.queues.recv(queue_name)
+        }
+
+        fn try_recv(&self, queue_name: &str) -> Result<Option<String>> {
+            self.queues.try_recv(queue_name)
+        }
+    }
+
+    #[test]
+    fn restarted_order_system_carries_on_from_the_next_order() {
+        let transport = Arc::new(FailingOnce { queues: InMemoryTransport::new(), failed: AtomicBool::new(false) });
+        let mut next_id = 1;
+        assert!(run_order(transport.clone(), &mut next_id, 4, Duration::from_millis(1)).is_err());
+        assert_eq!(next_id, 1);
+        run_order(transport.clone(), &mut next_id, 4, Duration::from_millis(1)).unwrap();
+        assert_eq!(next_id, 5);
+
+        let queue = broker_queue("payment").unwrap();
+        let mut sent = Vec::new();
+        while let Some(message) = transport.try_recv(&queue).unwrap() {
+            if let Envelope::Message(order) = serde_json::from_str::<Envelope<Order>>(&message).unwrap() {
+                sent.push(order.id);
+            }
+        }
+        assert_eq!(sent, [1, 2, 3, 4]);
+    }
+
+    // In-process queues that tag what they hand out and record the tags acknowledged
+    #[derive(Default)]
+    struct Acking {
+        queues: InMemoryTransport,
+        last_tag: AtomicU64,
+        acked: Mutex<Vec<u64>>,
+    }
+
+    impl MessageTransport for Acking {
+        fn send(&self, msg: String, queue_name: &str) -> Result<()> {
+            self.queues.send(msg, queue_name)
+        }
+
+        fn recv(&self, queue_name: &str) -> Result<String> {
+            self.queues.recv(queue_name)
+        }
+
+        fn try_recv(&self, queue_name: &str) -> Result<Option<String>> {
+            self.queues.try_recv(queue_name)
+        }
+
+        fn recv_unacked(&self, queue_name: &str) -> Result<(u64, String)> {
+            let message = self.queues.recv(queue_name)?;
+            Ok((self.last_tag.fetch_add(1, Ordering::SeqCst) + 1, message))
+        }
+
+        fn ack(&self, _queue_name: &str, tag: u64) -> Result<()> {
+            self.acked.lock().unwrap().push(tag);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn acknowledges_a_message_once_the_service_comes_back_for_the_next() {
+        let acking = Arc::new(Acking::default());
+        let address = Address::new("Nur Aisyah", &["12 Jalan Ampang"], "50450", "Kuala Lumpur", "Kuala Lumpur");
+        for id in 1..=2 {
+            send_queue(acking.as_ref(), &Order::new(id, Vec::new(), address.clone()), "payment").unwrap();
+        }
+        send_control(acking.as_ref(), Control::Shutdown, "payment").unwrap();
+        let acked = || acking.acked.lock().unwrap().clone();
+
+        let transport: Arc<dyn MessageTransport> = acking.clone();
+        let inbox = spawn_receiver(&transport, "payment").unwrap();
+        assert!(matches!(inbox.recv(), Ok((Envelope::Message(order), _)) if order.id == 1));
+        assert_eq!(acked(), Vec::<u64>::new());
+        assert!(matches!(inbox.recv(), Ok((Envelope::Message(order), _)) if order.id == 2));
+        assert_eq!(acked(), [1]);
+        assert!(matches!(inbox.recv(), Ok((Envelope::Control(Control::Shutdown), _))));
+        assert_eq!(acked(), [1, 2]);
+        drop(inbox);
+        assert_eq!(acked(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn answers_tracking_queries_while_running() {
+        let transport: Arc<dyn MessageTransport> = Arc::new(InMemoryTransport::new());
+        let tracking = {
+            let transport = Arc::clone(&transport);
+            thread::spawn(move || {
+                let mut log = TrackingLog::default();
+                run_tracking(transport, &mut log).map(|()| log)
+            })
+        };
+        let event = |stage, at| TrackingEvent { order_id: 3, stage, at, location: "Shah Alam".to_string() };
+        let events = [event(TrackingStage::LabelCreated, 1_000), event(TrackingStage::PickedUp, 2_000)];
+        for event in &events {
+            send_envelope(transport.as_ref(), &Envelope::Message(event), "tracking").unwrap();
+        }
+
+        let timeout = Duration::from_secs(5);
+        let mut history = Vec::new();
+        while history.len() < events.len() {
+            history = query_tracking(transport.as_ref(), 3, timeout).unwrap();
+        }
+        assert_eq!(history, events);
+        assert!(query_tracking(transport.as_ref(), 4, timeout).unwrap().is_empty());
+
+        for _ in 0..config::get().producers("tracking") {
+            send_control(transport.as_ref(), Control::Shutdown, "tracking").unwrap();
+        }
+        let log = tracking.join().unwrap().unwrap();
+        assert_eq!(log.history(3), events);
+    }
+}
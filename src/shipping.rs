@@ -0,0 +1,391 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::catalog::Catalog;
+use crate::dispatch::{default_couriers, Booking, CourierConfig};
+use crate::money::{Currency, Money, MoneyError};
+use crate::routing::TransportMode;
+use crate::structs::{unix_millis, Order};
+use crate::tracking::TrackingStage;
+use crate::warehouse::{WarehouseConfig, EAST_MALAYSIA_STATES, PENINSULAR_STATES};
+
+pub(crate) const HOUR_MS: u64 = 60 * 60 * 1000;
+pub(crate) const DAY_MS: u64 = 24 * HOUR_MS;
+// Malaysia time is UTC+8 all year round
+pub(crate) const MYT_OFFSET_MS: u64 = 8 * HOUR_MS;
+
+// Shipping zones; parcels crossing the South China Sea cost more and take longer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Zone {
+    Peninsular,
+    EastMalaysia,
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Peninsular => write!(f, "Peninsular Malaysia"),
+            Zone::EastMalaysia => write!(f, "East Malaysia"),
+        }
+    }
+}
+
+impl Zone {
+    pub fn of(state: &str) -> Option<Zone> {
+        let listed = |states: &[&str]| states.iter().any(|listed| listed.eq_ignore_ascii_case(state));
+        if listed(&PENINSULAR_STATES) {
+            Some(Zone::Peninsular)
+        } else if listed(&EAST_MALAYSIA_STATES) {
+            Some(Zone::EastMalaysia)
+        } else {
+            None
+        }
+    }
+
+    // A warehouse sits in the zone of the states it serves
+    pub fn of_warehouse(warehouse: &WarehouseConfig) -> Option<Zone> {
+        warehouse.states.iter().find_map(|state| Zone::of(state))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceLevel {
+    #[default]
+    Standard,
+    // Dearer, and in transit for half the days (rounded up)
+    Express,
+}
+
+impl fmt::Display for ServiceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceLevel::Standard => write!(f, "standard"),
+            ServiceLevel::Express => write!(f, "express"),
+        }
+    }
+}
+
+// What a courier charges for one transport mode
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rate {
+    // Charged per parcel, plus per kilogram with part kilograms rounded up
+    pub base: Money,
+    pub per_kg: Money,
+    // Added when the parcel crosses between Peninsular and East Malaysia
+    pub cross_zone_fee: Money,
+    // Days in transit within a zone and across zones
+    pub days: u32,
+    pub cross_zone_days: u32,
+    // Simulated hours from the label being created until the parcel is ready for a
+    // dispatch run, and from it going out for delivery to the delivery attempt
+    #[serde(default = "default_stage_hours")]
+    pub pickup_hours: u32,
+    #[serde(default = "default_stage_hours")]
+    pub delivery_hours: u32,
+}
+
+fn default_stage_hours() -> u32 {
+    4
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ShippingConfig {
+    pub land: Rate,
+    pub sea: Rate,
+    pub air: Rate,
+    // Added on top of the standard charge for express parcels
+    pub express_surcharge_percent: u32,
+    // Couriers, how many shipments each dispatch run takes and when the runs leave
+    pub couriers: Vec<CourierConfig>,
+}
+
+impl Default for ShippingConfig {
+    fn default() -> Self {
+        let myr = |minor| Money::new(minor, Currency::MYR);
+        let rate = |base, per_kg, cross_zone_fee, (days, cross_zone_days), (pickup_hours, delivery_hours)| Rate {
+            base: myr(base),
+            per_kg: myr(per_kg),
+            cross_zone_fee: myr(cross_zone_fee),
+            days,
+            cross_zone_days,
+            pickup_hours,
+            delivery_hours,
+        };
+        ShippingConfig {
+            land: rate(500, 100, 2500, (3, 8), (4, 4)),
+            sea: rate(800, 50, 1200, (5, 7), (12, 6)),
+            air: rate(1000, 400, 800, (1, 2), (2, 3)),
+            express_surcharge_percent: 50,
+            couriers: default_couriers(),
+        }
+    }
+}
+
+impl ShippingConfig {
+    pub fn rate(&self, mode: TransportMode) -> &Rate {
+        match mode {
+            TransportMode::Land => &self.land,
+            TransportMode::Sea => &self.sea,
+            TransportMode::Air => &self.air,
+        }
+    }
+
+    pub fn amounts(&self) -> impl Iterator<Item = Money> + '_ {
+        [&self.land, &self.sea, &self.air].into_iter().flat_map(|rate| [rate.base, rate.per_kg, rate.cross_zone_fee])
+    }
+}
+
+// The part of an order shipped from one warehouse
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Consignment {
+    pub warehouse: String,
+    pub origin: Option<Zone>,
+    pub weight_kg: f64,
+    pub cost: Money,
+    pub transit_days: u32,
+}
+
+impl fmt::Display for Consignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} kg from {} for {}, {} day(s)", self.weight_kg, self.warehouse, self.cost, self.transit_days)
+    }
+}
+
+// How an order travels to the customer and what it costs to send
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Shipment {
+    pub courier: String,
+    pub mode: TransportMode,
+    pub service_level: ServiceLevel,
+    pub destination: Option<Zone>,
+    pub consignments: Vec<Consignment>,
+    // Courier charges of all consignments
+    pub cost: Money,
+    // When the label was created and when the courier's run leaves, in milliseconds
+    // since the Unix epoch
+    pub dispatched_at: u64,
+    pub departs_at: u64,
+    // When the last consignment should arrive
+    pub estimated_delivery: u64,
+    // Latest tracking stage and its simulated time
+    pub stage: TrackingStage,
+    pub updated_at: u64,
+}
+
+impl fmt::Display for Shipment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} with {}, {}, estimated delivery {}",
+            self.mode,
+            self.service_level,
+            self.courier,
+            self.cost,
+            format_date(self.estimated_delivery)
+        )
+    }
+}
+
+impl Shipment {
+    // Quote sending the order on the booked run, one consignment per warehouse it ships
+    // from. A warehouse or address outside the known zones is charged as crossing zones.
+    pub fn quote(
+        order: &Order,
+        booking: &Booking,
+        catalog: &Catalog,
+        warehouses: &[WarehouseConfig],
+        rates: &ShippingConfig,
+    ) -> Result<Shipment, MoneyError> {
+        let rate = rates.rate(booking.mode);
+        let destination = Zone::of(&order.shipping_address.state);
+        let weight = |sku: &str, quantity: i32| catalog.find(sku).map_or(0.0, |product| product.weight_kg) * quantity as f64;
+
+        // Orders are only allocated once their stock is reserved; before that the
+        // whole order counts as one consignment from an unknown warehouse
+        let mut parcels: Vec<(String, f64)> = Vec::new();
+        if order.fulfilment.is_empty() {
+            let total = order.lines.iter().map(|line| weight(&line.sku, line.quantity)).sum();
+            parcels.push(("unallocated stock".to_string(), total));
+        }
+        for allocation in &order.fulfilment {
+            let kg = weight(&allocation.sku, allocation.quantity);
+            match parcels.iter_mut().find(|(warehouse, _)| *warehouse == allocation.warehouse) {
+                Some((_, total)) => *total += kg,
+                None => parcels.push((allocation.warehouse.clone(), kg)),
+            }
+        }
+
+        let mut consignments = Vec::with_capacity(parcels.len());
+        for (warehouse, weight_kg) in parcels {
+            let origin = warehouses.iter().find(|w| w.name == warehouse).and_then(Zone::of_warehouse);
+            let cross_zone = origin.is_none() || origin != destination;
+            let kilograms = weight_kg.ceil().max(1.0) as i32;
+            let mut cost = rate.base.checked_add(rate.per_kg.checked_times(kilograms)?)?;
+            let mut transit_days = rate.days;
+            if cross_zone {
+                cost = cost.checked_add(rate.cross_zone_fee)?;
+                transit_days = rate.cross_zone_days;
+            }
+            if order.service_level == ServiceLevel::Express {
+                cost = cost.checked_percent(100 + rates.express_surcharge_percent)?;
+                transit_days = transit_days.div_ceil(2);
+            }
+            consignments.push(Consignment { warehouse, origin, weight_kg, cost, transit_days: transit_days.max(1) });
+        }
+
+        let cost = Money::sum(rate.base.currency, consignments.iter().map(|c| c.cost))?;
+        let dispatched_at = unix_millis();
+        let days = consignments.iter().map(|c| c.transit_days).max().unwrap_or(0);
+        Ok(Shipment {
+            courier: booking.courier.clone(),
+            mode: booking.mode,
+            service_level: order.service_level,
+            destination,
+            consignments,
+            cost,
+            dispatched_at,
+            departs_at: booking.departure,
+            estimated_delivery: booking.departure + days as u64 * DAY_MS,
+            stage: TrackingStage::LabelCreated,
+            updated_at: dispatched_at,
+        })
+    }
+}
+
+// "2026-10-21" for milliseconds since the Unix epoch, as a date in Malaysia
+pub fn format_date(millis: u64) -> String {
+    let (year, month, day) = civil_date(millis + MYT_OFFSET_MS);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// "2026-10-21 14:05", in Malaysia time
+pub fn format_time(millis: u64) -> String {
+    let local = millis + MYT_OFFSET_MS;
+    let minutes = local % DAY_MS / 60_000;
+    format!("{} {:02}:{:02}", format_date(millis), minutes / 60, minutes % 60)
+}
+
+// Year, month and day of a day count since 1970-01-01, after Howard Hinnant's
+// civil_from_days
+fn civil_date(millis: u64) -> (i64, i64, i64) {
+    let days = (millis / DAY_MS) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::catalog::Product;
+    use crate::structs::OrderLine;
+    use crate::warehouse::{default_warehouses, Allocation};
+
+    const DEPARTURE: u64 = 1_792_368_000_000;
+
+    fn catalog() -> Catalog {
+        let product = |sku: &str, weight_kg| Product {
+            sku: sku.to_string(),
+            name: sku.to_string(),
+            price: Money::new(1000, Currency::MYR),
+            sst_percent: 10,
+            weight_kg,
+            max_capacity: 100,
+            reorder_point: None,
+            order_up_to: None,
+        };
+        Catalog { products: vec![product("TS-001", 1.2), product("MG-001", 0.4)] }
+    }
+
+    // An order to Kota Kinabalu, Sabah, shipped from the given warehouses
+    fn order(service_level: ServiceLevel, fulfilment: &[(&str, &str, i32)]) -> Order {
+        let address = Address::new("Dayang Nurul", &["5 Persiaran Damai"], "88000", "Kota Kinabalu", "Sabah");
+        let lines = fulfilment.iter().map(|&(_, sku, quantity)| OrderLine::new(sku, quantity)).collect();
+        let mut order = Order::new(1, lines, address);
+        order.service_level = service_level;
+        order.fulfilment = fulfilment
+            .iter()
+            .map(|&(warehouse, sku, quantity)| Allocation { warehouse: warehouse.to_string(), sku: sku.to_string(), quantity })
+            .collect();
+        order
+    }
+
+    fn quote(order: &Order) -> Shipment {
+        let booking = Booking { courier: "PosLaju".to_string(), mode: TransportMode::Land, departure: DEPARTURE };
+        Shipment::quote(order, &booking, &catalog(), &default_warehouses(), &ShippingConfig::default()).unwrap()
+    }
+
+    fn myr(minor: i64) -> Money {
+        Money::new(minor, Currency::MYR)
+    }
+
+    #[test]
+    fn charges_the_cross_zone_fee_only_across_zones() {
+        // 2.4 kg is charged as 3 kg: 5.00 + 3 x 1.00, plus 25.00 from the peninsula
+        let within = quote(&order(ServiceLevel::Standard, &[("Kota Kinabalu", "TS-001", 2)]));
+        assert_eq!((within.cost, within.consignments[0].transit_days), (myr(800), 3));
+        let across = quote(&order(ServiceLevel::Standard, &[("Shah Alam", "TS-001", 2)]));
+        assert_eq!((across.cost, across.consignments[0].transit_days), (myr(3300), 8));
+        assert_eq!(across.consignments[0].origin, Some(Zone::Peninsular));
+        assert_eq!(across.destination, Some(Zone::EastMalaysia));
+    }
+
+    #[test]
+    fn express_halves_transit_days_rounding_up() {
+        let within = quote(&order(ServiceLevel::Express, &[("Kota Kinabalu", "TS-001", 2)]));
+        assert_eq!((within.cost, within.consignments[0].transit_days), (myr(1200), 2));
+        let across = quote(&order(ServiceLevel::Express, &[("Shah Alam", "TS-001", 2)]));
+        assert_eq!((across.cost, across.consignments[0].transit_days), (myr(4950), 4));
+        assert_eq!(across.estimated_delivery, DEPARTURE + 4 * DAY_MS);
+    }
+
+    #[test]
+    fn ships_one_consignment_per_warehouse() {
+        let order = order(
+            ServiceLevel::Standard,
+            &[("Shah Alam", "TS-001", 1), ("Kota Kinabalu", "TS-001", 2), ("Shah Alam", "MG-001", 1)],
+        );
+        let shipment = quote(&order);
+        let warehouses: Vec<&str> = shipment.consignments.iter().map(|c| c.warehouse.as_str()).collect();
+        assert_eq!(warehouses, ["Shah Alam", "Kota Kinabalu"]);
+        // 1.6 kg from Shah Alam is charged as 2 kg
+        assert!((shipment.consignments[0].weight_kg - 1.6).abs() < 1e-9);
+        assert_eq!(shipment.consignments[0].cost, myr(3200));
+        assert_eq!(shipment.consignments[1].cost, myr(800));
+        assert_eq!(shipment.cost, myr(4000));
+        // The slowest consignment sets the estimate
+        assert_eq!(shipment.estimated_delivery, DEPARTURE + 8 * DAY_MS);
+    }
+
+    #[test]
+    fn converts_days_to_dates_across_boundaries() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(1_769_817_600_000), (2026, 1, 31));
+        assert_eq!(civil_date(1_769_904_000_000), (2026, 2, 1));
+        assert_eq!(civil_date(1_703_980_800_000 + DAY_MS - 1), (2023, 12, 31));
+        assert_eq!(civil_date(1_703_980_800_000 + DAY_MS), (2024, 1, 1));
+        // Leap days, including the 400-year rule, and 2100 which has none
+        assert_eq!(civil_date(1_709_164_800_000), (2024, 2, 29));
+        assert_eq!(civil_date(1_709_251_200_000), (2024, 3, 1));
+        assert_eq!(civil_date(951_782_400_000), (2000, 2, 29));
+        assert_eq!(civil_date(4_107_542_400_000 - DAY_MS), (2100, 2, 28));
+    }
+
+    #[test]
+    fn formats_dates_in_malaysia_time() {
+        // 2025-12-31 16:00 UTC is midnight on New Year's Day in Malaysia
+        let new_year = 1_767_196_800_000;
+        assert_eq!(format_date(new_year - 1), "2025-12-31");
+        assert_eq!(format_date(new_year), "2026-01-01");
+        assert_eq!(format_time(new_year - 60_000), "2025-12-31 23:59");
+        assert_eq!(format_time(new_year + 14 * HOUR_MS + 5 * 60_000), "2026-01-01 14:05");
+    }
+}
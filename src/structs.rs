@@ -1,67 +1,584 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
+use crate::address::Address;
+use crate::catalog::{self, Catalog};
+use crate::money::Money;
+use crate::pricing::Totals;
+use crate::journal::{Journal, JournalEntry, JournalError};
+use crate::replenishment::{PurchaseOrder, DEFAULT_LEAD_TIME};
+use crate::shipping::{ServiceLevel, Shipment};
+use crate::warehouse::Allocation;
 
-#[derive(Serialize, Deserialize, Debug)]
+// One product of an order and how many of it were ordered
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderLine {
+    pub sku: String,
+    pub quantity: i32,
+    // Catalog price of one unit and the SST on the whole line, filled in by pricing
+    #[serde(default)]
+    pub unit_price: Money,
+    #[serde(default)]
+    pub tax: Money,
+}
+
+impl OrderLine {
+    pub fn new(sku: &str, quantity: i32) -> Self {
+        OrderLine { sku: sku.to_string(), quantity, unit_price: Money::default(), tax: Money::default() }
+    }
+}
+
+impl fmt::Display for OrderLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} x{}", self.sku, self.quantity)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Order {
     pub id: i32,
-    pub item: String,
-    pub quantity: i32,
-    pub shipping_address: String,
-    pub payment_status: bool,
-    pub delivery_status: bool,
-    pub final_status: String,
+    pub lines: Vec<OrderLine>,
+    pub shipping_address: Address,
+    pub status: OrderStatus,
+    // Milliseconds since the Unix epoch, 0 when unknown
+    #[serde(default)]
+    pub created_at: u64,
+    // Retries made by the monitoring system so far, carried along with the order
+    #[serde(default)]
+    pub payment_retries: u32,
+    #[serde(default)]
+    pub delivery_retries: u32,
+    // Set by the payment system when a paid order is cancelled
+    #[serde(default)]
+    pub refund: Option<RefundStatus>,
+    // Why the order was cancelled, set by the saga coordinator, or rejected at intake
+    #[serde(default)]
+    pub cancel_reason: Option<String>,
+    // Warehouses the order ships from, set once its stock is reserved
+    #[serde(default)]
+    pub fulfilment: Vec<Allocation>,
+    // Filled in by pricing when the order is taken
+    #[serde(default)]
+    pub totals: Totals,
+    #[serde(default)]
+    pub service_level: ServiceLevel,
+    // Courier, cost and estimated delivery, set by the delivery system
+    #[serde(default)]
+    pub shipment: Option<Shipment>,
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+impl Order {
+    pub fn new(id: i32, lines: Vec<OrderLine>, shipping_address: Address) -> Self {
+        Order {
+            id,
+            lines,
+            shipping_address,
+            status: OrderStatus::Pending,
+            created_at: unix_millis(),
+            payment_retries: 0,
+            delivery_retries: 0,
+            refund: None,
+            cancel_reason: None,
+            fulfilment: Vec::new(),
+            totals: Totals::default(),
+            service_level: ServiceLevel::Standard,
+            shipment: None,
+        }
+    }
+
+    // An order must have at least one line and order a positive quantity on every line
+    pub fn validate_lines(&self) -> Result<(), String> {
+        if self.lines.is_empty() {
+            return Err("order has no lines".to_string());
+        }
+        match self.lines.iter().find(|line| line.quantity <= 0) {
+            Some(line) => Err(format!("invalid quantity {} for {}", line.quantity, line.sku)),
+            None => Ok(()),
+        }
+    }
+
+    // Quantity ordered of `sku` across all lines
+    pub fn quantity_of(&self, sku: &str) -> i32 {
+        self.lines.iter().filter(|line| line.sku == sku).map(|line| line.quantity).sum()
+    }
+
+    // "TS-001 x2, SC-001 x1"
+    pub fn describe_lines(&self) -> String {
+        self.lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    // Time since the order was created, if known
+    pub fn age(&self) -> Option<Duration> {
+        if self.created_at == 0 {
+            return None;
+        }
+        Some(Duration::from_millis(unix_millis().saturating_sub(self.created_at)))
+    }
+
+    // Move the order to the next status, rejecting transitions the lifecycle does not allow
+    pub fn transition(&mut self, next: OrderStatus) -> Result<(), InvalidTransition> {
+        if !self.status.can_transition_to(next) {
+            return Err(InvalidTransition { order_id: self.id, from: self.status, to: next });
+        }
+        self.status = next;
+        Ok(())
+    }
+}
+
+// Lifecycle of an order, serialized as the variant name (e.g. "Pending")
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Reserved,
+    Shipped,
+    Delivered,
+    PaymentFailed,
+    DeliveryFailed,
+    Cancelled,
+    Returned,
+    // Refused when taken, e.g. for an invalid address; never paid for
+    Rejected,
+}
+
+impl OrderStatus {
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Paid | PaymentFailed | Cancelled | Rejected)
+                | (PaymentFailed, Paid | Cancelled)
+                | (Paid, Reserved | Cancelled)
+                | (Reserved, Shipped | Cancelled)
+                | (Shipped, Delivered | DeliveryFailed | Cancelled)
+                | (DeliveryFailed, Delivered | Cancelled)
+                | (Delivered, Returned)
+        )
+    }
+
+    pub fn is_final(self) -> bool {
+        matches!(self, OrderStatus::Delivered | OrderStatus::Cancelled | OrderStatus::Returned | OrderStatus::Rejected)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "result", content = "reason", rename_all = "lowercase")]
+pub enum RefundStatus {
+    Refunded,
+    Failed(String),
+}
+
+impl fmt::Display for RefundStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefundStatus::Refunded => write!(f, "refunded"),
+            RefundStatus::Failed(reason) => write!(f, "refund failed ({})", reason),
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub order_id: i32,
+    pub from: OrderStatus,
+    pub to: OrderStatus,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[Order ID {}] Illegal status transition {} -> {}", self.order_id, self.from, self.to)
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+// Wire format for every queue: either a domain message or a control message
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Envelope<T> {
+    Message(T),
+    Control(Control),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    // Stop once every upstream producer has shut down, then propagate downstream
+    Shutdown,
+    // Finish the messages already received, then shut down regardless of other producers
+    Drain,
+    // Hold incoming messages until Resume
+    Pause,
+    Resume,
+    // Liveness check, answered in the service log
+    Ping,
+    // Stop delivering the order and drop its pending retries; its saga has been compensated
+    Recall(i32),
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ItemStock {
-    pub name: &'static str,
+    pub sku: String,
+    // On hand, including stock reserved for orders not yet delivered
     pub quantity: i32,
+    pub max_capacity: i32,
+    // A purchase order is placed once the stock position falls to the reorder point,
+    // for enough to bring it back up to the order-up-to level
+    pub reorder_point: i32,
+    pub order_up_to: i32,
+}
+
+// Stock held back for an order until its delivery succeeds or the hold expires
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    pub lines: Vec<OrderLine>,
+    pub expires_at: Instant,
 }
 
 pub struct Inventory {
     pub stocks: Vec<ItemStock>,
+    // Keyed by order id
+    pub reservations: HashMap<i32, Reservation>,
+    pub reservation_ttl: Duration,
+    // Placed with the supplier and not yet received
+    pub purchase_orders: Vec<PurchaseOrder>,
+    pub lead_time: Duration,
+    pub(crate) next_purchase_order: u32,
+    // Every change is written here before it is applied, when persistence is enabled
+    journal: Option<Journal>,
 }
 
-pub const MAX_CAPACITY: i32 = 10;
+pub const DEFAULT_RESERVATION_TTL: Duration = Duration::from_secs(180);
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Inventory {
+    // Every product in the process-wide catalog, filled to capacity
     pub fn new() -> Self {
-        let items = vec![
-            ItemStock { name: "T-Shirt", quantity: MAX_CAPACITY },
-            ItemStock { name: "Hoodie", quantity: MAX_CAPACITY },
-            ItemStock { name: "Skirt", quantity: MAX_CAPACITY },
-            ItemStock { name: "Dress", quantity: MAX_CAPACITY },
-            ItemStock { name: "Wallet", quantity: MAX_CAPACITY },
-            ItemStock { name: "Shoes", quantity: MAX_CAPACITY },
-            ItemStock { name: "Socks", quantity: MAX_CAPACITY },
-            ItemStock { name: "Pants", quantity: MAX_CAPACITY },
-            ItemStock { name: "Shorts", quantity: MAX_CAPACITY },
-        ];
-        Inventory { stocks: items }
+        Self::from_catalog(catalog::get())
+    }
+
+    pub fn from_catalog(catalog: &Catalog) -> Self {
+        let items = catalog
+            .products
+            .iter()
+            .map(|product| ItemStock {
+                sku: product.sku.clone(),
+                quantity: product.max_capacity,
+                max_capacity: product.max_capacity,
+                reorder_point: product.reorder_point(),
+                order_up_to: product.order_up_to(),
+            })
+            .collect();
+        Inventory {
+            stocks: items,
+            reservations: HashMap::new(),
+            reservation_ttl: DEFAULT_RESERVATION_TTL,
+            purchase_orders: Vec::new(),
+            lead_time: DEFAULT_LEAD_TIME,
+            next_purchase_order: 1,
+            journal: None,
+        }
+    }
+
+    pub fn with_reservation_ttl(mut self, ttl: Duration) -> Self {
+        self.reservation_ttl = ttl;
+        self
+    }
+
+    // Recover the state recorded in the journal at `path`, then keep recording to it.
+    // The journal is compacted to a snapshot of the recovered state on the way.
+    pub fn with_journal(mut self, path: &str) -> Result<Self, JournalError> {
+        let entries = Journal::read(path)?;
+        println!("[Journal] Replaying {} records from {}", entries.len(), path);
+        for entry in entries {
+            self.apply(entry);
+        }
+        self.journal = Some(Journal::rewrite(path, &self.snapshot())?);
+        Ok(self)
     }
 
     pub fn is_stock_available(&self, item: &str, quantity: i32) -> bool {
-        self.stocks.iter().any(|stock| stock.name == item && stock.quantity >= quantity)
+        quantity > 0 && self.stocks.iter().any(|stock| stock.sku == item) && self.available(item) >= quantity
+    }
+
+    pub fn on_hand(&self, item: &str) -> i32 {
+        self.get_stock(item)
+    }
+
+    pub fn reserved(&self, item: &str) -> i32 {
+        self.reservations.values().flat_map(|r| &r.lines).filter(|line| line.sku == item).map(|line| line.quantity).sum()
     }
 
-    pub fn deduct_stock(&mut self, item: &str, quantity: i32) {
-        if let Some(stock) = self.stocks.iter_mut().find(|stock| stock.name == item) {
-            stock.quantity -= quantity;
+    // On hand and not reserved for another order
+    pub fn available(&self, item: &str) -> i32 {
+        self.on_hand(item) - self.reserved(item)
+    }
+
+    // Stock of one item held for an order
+    pub fn held(&self, order_id: i32, item: &str) -> i32 {
+        self.reservations
+            .get(&order_id)
+            .map_or(0, |r| r.lines.iter().filter(|line| line.sku == item).map(|line| line.quantity).sum())
+    }
+
+    // Hold stock of one item for an order, failing when not enough is available. An
+    // order holding several items makes one call per item. Reserving an item the order
+    // already holds replaces that hold, so a redelivered order is not counted twice.
+    pub fn reserve(&mut self, order_id: i32, item: &str, quantity: i32) -> Result<bool, JournalError> {
+        // The order's own hold on the item is given back before checking
+        let held = self.held(order_id, item);
+        if quantity <= 0 || !self.stocks.iter().any(|stock| stock.sku == item) || self.available(item) + held < quantity {
+            return Ok(false);
         }
+        let expires_at_ms = unix_millis() + self.reservation_ttl.as_millis() as u64;
+        self.record(JournalEntry::Reserve { order_id, sku: item.to_string(), quantity, expires_at_ms })?;
+        Ok(true)
+    }
+
+    // The order has been delivered: take its reserved stock off the shelf for good
+    pub fn commit(&mut self, order_id: i32) -> Result<Option<Reservation>, JournalError> {
+        let Some(reservation) = self.reservations.get(&order_id).cloned() else {
+            return Ok(None);
+        };
+        self.record(JournalEntry::Commit { order_id })?;
+        Ok(Some(reservation))
+    }
+
+    // The order will not be delivered: make its reserved stock available again
+    pub fn release(&mut self, order_id: i32) -> Result<Option<Reservation>, JournalError> {
+        let Some(reservation) = self.reservations.get(&order_id).cloned() else {
+            return Ok(None);
+        };
+        self.record(JournalEntry::Release { order_id })?;
+        Ok(Some(reservation))
     }
 
-    pub fn add_stock(&mut self, item: &str, quantity: i32) {
-        if let Some(stock) = self.stocks.iter_mut().find(|stock| stock.name == item) {
-            stock.quantity += quantity;
+    // Release every reservation past its expiry, returning them by order id
+    pub fn expire(&mut self) -> Result<Vec<(i32, Reservation)>, JournalError> {
+        let now = Instant::now();
+        let expired: Vec<i32> = self
+            .reservations
+            .iter()
+            .filter(|(_, r)| r.expires_at <= now)
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        let mut released = Vec::with_capacity(expired.len());
+        for order_id in expired {
+            if let Some(reservation) = self.release(order_id)? {
+                released.push((order_id, reservation));
+            }
         }
+        Ok(released)
+    }
+
+    pub fn deduct_stock(&mut self, item: &str, quantity: i32) -> Result<(), JournalError> {
+        self.record(JournalEntry::Deduct { sku: item.to_string(), quantity })
+    }
+
+    pub fn add_stock(&mut self, item: &str, quantity: i32) -> Result<(), JournalError> {
+        self.record(JournalEntry::Add { sku: item.to_string(), quantity })
     }
 
     pub fn get_stock(&self, item: &str) -> i32 {
-        self.stocks.iter().find(|stock| stock.name == item).map_or(0, |stock| stock.quantity)
+        self.stocks.iter().find(|stock| stock.sku == item).map_or(0, |stock| stock.quantity)
+    }
+
+    // Write the change ahead to the journal, then apply it
+    pub(crate) fn record(&mut self, entry: JournalEntry) -> Result<(), JournalError> {
+        if let Some(journal) = &mut self.journal {
+            journal.append(&entry)?;
+        }
+        self.apply(entry);
+        Ok(())
     }
 
-    pub fn restock(&mut self, item: &str) {
-        if let Some(stock) = self.stocks.iter_mut().find(|stock| stock.name == item) {
-            stock.quantity = MAX_CAPACITY;
+    fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Set { sku, quantity } => {
+                if let Some(stock) = self.stocks.iter_mut().find(|stock| stock.sku == sku) {
+                    stock.quantity = quantity;
+                }
+            }
+            JournalEntry::Deduct { sku, quantity } => {
+                if let Some(stock) = self.stocks.iter_mut().find(|stock| stock.sku == sku) {
+                    stock.quantity -= quantity;
+                }
+            }
+            JournalEntry::Add { sku, quantity } => {
+                if let Some(stock) = self.stocks.iter_mut().find(|stock| stock.sku == sku) {
+                    stock.quantity += quantity;
+                }
+            }
+            JournalEntry::Reserve { order_id, sku, quantity, expires_at_ms } => {
+                let remaining = Duration::from_millis(expires_at_ms.saturating_sub(unix_millis()));
+                let expires_at = Instant::now() + remaining;
+                let reservation = self.reservations.entry(order_id).or_insert(Reservation { lines: Vec::new(), expires_at });
+                reservation.lines.retain(|line| line.sku != sku);
+                reservation.lines.push(OrderLine::new(&sku, quantity));
+                reservation.expires_at = expires_at;
+            }
+            JournalEntry::Commit { order_id } => {
+                if let Some(reservation) = self.reservations.remove(&order_id) {
+                    for line in reservation.lines {
+                        self.apply(JournalEntry::Deduct { sku: line.sku, quantity: line.quantity });
+                    }
+                }
+            }
+            JournalEntry::Release { order_id } => {
+                self.reservations.remove(&order_id);
+            }
+            JournalEntry::PurchaseOrder { id, sku, quantity, arrives_at_ms } => {
+                let remaining = Duration::from_millis(arrives_at_ms.saturating_sub(unix_millis()));
+                self.purchase_orders.push(PurchaseOrder { id, sku, quantity, arrives_at: Instant::now() + remaining });
+                self.next_purchase_order = self.next_purchase_order.max(id + 1);
+            }
+            JournalEntry::Receive { id } => {
+                if let Some(index) = self.purchase_orders.iter().position(|po| po.id == id) {
+                    let po = self.purchase_orders.remove(index);
+                    self.apply(JournalEntry::Add { sku: po.sku, quantity: po.quantity });
+                }
+            }
         }
     }
+
+    // Records that rebuild the current state from the catalog's starting point
+    fn snapshot(&self) -> Vec<JournalEntry> {
+        let now = Instant::now();
+        let stocks = self
+            .stocks
+            .iter()
+            .map(|stock| JournalEntry::Set { sku: stock.sku.clone(), quantity: stock.quantity });
+        let reservations = self.reservations.iter().flat_map(|(order_id, r)| {
+            r.lines.iter().map(move |line| JournalEntry::Reserve {
+                order_id: *order_id,
+                sku: line.sku.clone(),
+                quantity: line.quantity,
+                expires_at_ms: unix_millis() + r.expires_at.saturating_duration_since(now).as_millis() as u64,
+            })
+        });
+        let purchase_orders = self.purchase_orders.iter().map(|po| JournalEntry::PurchaseOrder {
+            id: po.id,
+            sku: po.sku.clone(),
+            quantity: po.quantity,
+            arrives_at_ms: unix_millis() + po.arrives_at.saturating_duration_since(now).as_millis() as u64,
+        });
+        stocks.chain(reservations).chain(purchase_orders).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_only_the_transitions_in_the_lifecycle() {
+        use OrderStatus::*;
+        const ALL: [OrderStatus; 10] = [Pending, Paid, Reserved, Shipped, Delivered, PaymentFailed, DeliveryFailed, Cancelled, Returned, Rejected];
+        const ALLOWED: [(OrderStatus, OrderStatus); 16] = [
+            (Pending, Paid), (Pending, PaymentFailed), (Pending, Cancelled), (Pending, Rejected),
+            (PaymentFailed, Paid), (PaymentFailed, Cancelled),
+            (Paid, Reserved), (Paid, Cancelled),
+            (Reserved, Shipped), (Reserved, Cancelled),
+            (Shipped, Delivered), (Shipped, DeliveryFailed), (Shipped, Cancelled),
+            (DeliveryFailed, Delivered), (DeliveryFailed, Cancelled),
+            (Delivered, Returned),
+        ];
+        let address = Address::new("Nur Aisyah", &["12 Jalan Ampang"], "50450", "Kuala Lumpur", "Kuala Lumpur");
+        for from in ALL {
+            for to in ALL {
+                let mut order = Order::new(1, Vec::new(), address.clone());
+                order.status = from;
+                let allowed = ALLOWED.contains(&(from, to));
+                match order.transition(to) {
+                    Ok(()) => assert!(allowed, "{} -> {} should be rejected", from, to),
+                    Err(e) => {
+                        assert!(!allowed, "{} -> {} should be allowed", from, to);
+                        assert_eq!(e, InvalidTransition { order_id: 1, from, to });
+                        assert_eq!(order.status, from);
+                    }
+                }
+            }
+        }
+        // Nothing leaves a final status but a return of a delivered order
+        for from in ALL.into_iter().filter(|status| status.is_final() && *status != Delivered) {
+            assert!(ALL.iter().all(|to| !from.can_transition_to(*to)), "{} is final", from);
+        }
+        assert!(!Delivered.can_transition_to(Cancelled));
+        assert!(!Pending.can_transition_to(Shipped));
+    }
+
+    #[test]
+    fn orders_need_lines_with_positive_quantities() {
+        let address = Address::new("Nur Aisyah", &["12 Jalan Ampang"], "50450", "Kuala Lumpur", "Kuala Lumpur");
+        let order = |lines: Vec<OrderLine>| Order::new(1, lines, address.clone());
+        assert!(order(vec![OrderLine::new("SKU-1", 1), OrderLine::new("SKU-2", 5)]).validate_lines().is_ok());
+        assert!(order(Vec::new()).validate_lines().is_err());
+        assert!(order(vec![OrderLine::new("SKU-1", 1), OrderLine::new("SKU-2", 0)]).validate_lines().is_err());
+        assert!(order(vec![OrderLine::new("SKU-1", -2)]).validate_lines().is_err());
+    }
+
+    fn inventory() -> (Inventory, String, i32) {
+        let inventory = Inventory::new();
+        let stock = inventory.stocks[0].clone();
+        (inventory, stock.sku, stock.quantity)
+    }
+
+    #[test]
+    fn rejects_quantities_that_are_not_positive() {
+        let (mut inventory, sku, _) = inventory();
+        for quantity in [0, -1] {
+            assert!(!inventory.is_stock_available(&sku, quantity));
+            assert!(!inventory.reserve(1, &sku, quantity).unwrap());
+        }
+        assert!(!inventory.reserve(1, "NO-SUCH-SKU", 1).unwrap());
+        assert!(inventory.reservations.is_empty());
+    }
+
+    #[test]
+    fn reserving_again_replaces_the_hold() {
+        let (mut inventory, sku, on_hand) = inventory();
+        assert!(inventory.reserve(1, &sku, on_hand).unwrap());
+        // The order's own hold does not count against it
+        assert!(inventory.reserve(1, &sku, on_hand).unwrap());
+        assert_eq!(inventory.reserved(&sku), on_hand);
+        assert!(inventory.reserve(1, &sku, 1).unwrap());
+        assert_eq!((inventory.reserved(&sku), inventory.available(&sku)), (1, on_hand - 1));
+
+        // Another order only gets what is left
+        assert!(!inventory.reserve(2, &sku, on_hand).unwrap());
+        assert!(inventory.reserve(2, &sku, on_hand - 1).unwrap());
+        assert_eq!(inventory.available(&sku), 0);
+    }
+
+    #[test]
+    fn releases_holds_once_they_expire() {
+        let (inventory, sku, on_hand) = inventory();
+        let mut inventory = inventory.with_reservation_ttl(Duration::ZERO);
+        assert!(inventory.reserve(1, &sku, 2).unwrap());
+        let expired = inventory.expire().unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, 1);
+        assert_eq!((inventory.reserved(&sku), inventory.on_hand(&sku)), (0, on_hand));
+        assert!(inventory.expire().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::shipping::{format_time, Rate, Shipment, DAY_MS, HOUR_MS};
+
+// Where a shipment is on its way to the customer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrackingStage {
+    #[default]
+    LabelCreated,
+    PickedUp,
+    InTransit,
+    OutForDelivery,
+    Delivered,
+    // The courier could not hand the parcel over; it goes out again the next day
+    FailedAttempt,
+}
+
+impl fmt::Display for TrackingStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self {
+            TrackingStage::LabelCreated => "label created",
+            TrackingStage::PickedUp => "picked up",
+            TrackingStage::InTransit => "in transit",
+            TrackingStage::OutForDelivery => "out for delivery",
+            TrackingStage::Delivered => "delivered",
+            TrackingStage::FailedAttempt => "delivery attempt failed",
+        };
+        write!(f, "{}", stage)
+    }
+}
+
+// Published on the tracking queue each time a shipment moves to another stage
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackingEvent {
+    pub order_id: i32,
+    pub stage: TrackingStage,
+    // Simulated time of the event, in milliseconds since the Unix epoch
+    pub at: u64,
+    // Where the parcel is, e.g. the warehouse it left or the destination state
+    pub location: String,
+}
+
+impl fmt::Display for TrackingEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {:<23} {}", format_time(self.at), self.stage.to_string(), self.location)
+    }
+}
+
+impl Shipment {
+    // Simulated times of the stages leading up to the first delivery attempt, and of
+    // the attempt itself, which happens on the estimated delivery date. The courier
+    // collects the parcel when its run leaves.
+    pub fn first_attempt(&self, rate: &Rate) -> ([(TrackingStage, u64); 3], u64) {
+        let picked_up = self.departs_at;
+        let in_transit = picked_up + HOUR_MS;
+        let delivery_window = rate.delivery_hours as u64 * HOUR_MS;
+        let out_for_delivery = self.estimated_delivery.saturating_sub(delivery_window).max(in_transit + HOUR_MS);
+        let stages = [
+            (TrackingStage::PickedUp, picked_up),
+            (TrackingStage::InTransit, in_transit),
+            (TrackingStage::OutForDelivery, out_for_delivery),
+        ];
+        (stages, out_for_delivery + delivery_window)
+    }
+
+    // Simulated times of going out for delivery again and of the attempt, the day
+    // after the last one
+    pub fn next_attempt(&self, rate: &Rate) -> (u64, u64) {
+        let attempt = self.updated_at + DAY_MS;
+        (attempt - rate.delivery_hours as u64 * HOUR_MS, attempt)
+    }
+
+    // Move the shipment to `stage`, returning the event to publish
+    pub fn advance(&mut self, order_id: i32, stage: TrackingStage, at: u64, location: &str) -> TrackingEvent {
+        self.stage = stage;
+        self.updated_at = at;
+        TrackingEvent { order_id, stage, at, location: location.to_string() }
+    }
+}
+
+// Asks the running tracking system for an order's history, which it publishes as a
+// TrackingReply on the broker queue `reply_to`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackingQuery {
+    pub order_id: i32,
+    pub reply_to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackingReply {
+    pub order_id: i32,
+    // Oldest first; empty when no event of the order has been received yet
+    pub events: Vec<TrackingEvent>,
+}
+
+// Tracking events received so far, by order id
+#[derive(Debug, Default)]
+pub struct TrackingLog {
+    events: BTreeMap<i32, Vec<TrackingEvent>>,
+}
+
+impl TrackingLog {
+    pub fn record(&mut self, event: TrackingEvent) {
+        self.events.entry(event.order_id).or_default().push(event);
+    }
+
+    // Every event of the order's shipment, oldest first
+    pub fn history(&self, order_id: i32) -> &[TrackingEvent] {
+        self.events.get(&order_id).map_or(&[], |events| events.as_slice())
+    }
+
+    pub fn order_ids(&self) -> impl Iterator<Item = i32> + '_ {
+        self.events.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
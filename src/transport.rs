@@ -0,0 +1,153 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use crate::config::BrokerConfig;
+use crate::error::{Error, Result};
+use crate::rabbitmq::{Publisher, Subscriber};
+
+// Abstraction over the message broker so the services can run without RabbitMQ
+pub trait MessageTransport: Send + Sync {
+    fn send(&self, msg: String, queue_name: &str) -> Result<()>;
+
+    // Blocks until a message is available on the queue
+    fn recv(&self, queue_name: &str) -> Result<String>;
+
+    // Takes the next message if one is waiting, without blocking
+    fn try_recv(&self, queue_name: &str) -> Result<Option<String>>;
+
+    // Like `recv`, but the message is only acknowledged once `ack` is called with the
+    // tag returned alongside it, so the broker redelivers it if the consumer dies first.
+    // Transports without acknowledgements return tag 0 and ignore acks.
+    fn recv_unacked(&self, queue_name: &str) -> Result<(u64, String)> {
+        Ok((0, self.recv(queue_name)?))
+    }
+
+    fn ack(&self, _queue_name: &str, _tag: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Transport backed by the RabbitMQ broker, reusing one publisher connection per
+// thread and one streaming subscriber per queue for the lifetime of the service
+pub struct RabbitTransport {
+    url: String,
+    // Each thread publishes over its own connection, so service threads sharing the
+    // transport do not queue up behind one another
+    publishers: Mutex<HashMap<ThreadId, Arc<Mutex<Publisher>>>>,
+    subscribers: Mutex<HashMap<String, Arc<Subscriber>>>,
+}
+
+impl RabbitTransport {
+    pub fn new(broker: &BrokerConfig) -> Self {
+        RabbitTransport {
+            url: broker.url(),
+            publishers: Mutex::new(HashMap::new()),
+            subscribers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The calling thread's publisher, opened on its first use
+    fn publisher(&self) -> Result<Arc<Mutex<Publisher>>> {
+        let mut publishers = self.publishers.lock().map_err(|_| poisoned("publisher table"))?;
+        let publisher = publishers
+            .entry(thread::current().id())
+            .or_insert_with(|| Arc::new(Mutex::new(Publisher::new(&self.url))));
+        Ok(Arc::clone(publisher))
+    }
+
+    fn subscriber(&self, queue_name: &str) -> Result<Arc<Subscriber>> {
+        let mut subscribers = self.subscribers.lock().map_err(|_| poisoned("subscriber table"))?;
+        let subscriber = subscribers
+            .entry(queue_name.to_string())
+            .or_insert_with(|| Arc::new(Subscriber::start(&self.url, queue_name)));
+        Ok(Arc::clone(subscriber))
+    }
+}
+
+impl MessageTransport for RabbitTransport {
+    fn send(&self, msg: String, queue_name: &str) -> Result<()> {
+        let publisher = self.publisher()?;
+        let mut publisher = publisher.lock().map_err(|_| poisoned("publisher"))?;
+        Ok(publisher.publish(&msg, queue_name)?)
+    }
+
+    fn recv(&self, queue_name: &str) -> Result<String> {
+        let subscriber = self.subscriber(queue_name)?;
+        let (tag, message) = subscriber.recv()?;
+        subscriber.ack(tag)?;
+        Ok(message)
+    }
+
+    fn recv_unacked(&self, queue_name: &str) -> Result<(u64, String)> {
+        self.subscriber(queue_name)?.recv()
+    }
+
+    fn ack(&self, queue_name: &str, tag: u64) -> Result<()> {
+        self.subscriber(queue_name)?.ack(tag)
+    }
+
+    fn try_recv(&self, queue_name: &str) -> Result<Option<String>> {
+        // Messages a running subscriber has already taken off the broker come first
+        let subscriber = self.subscribers.lock().map_err(|_| poisoned("subscriber table"))?.get(queue_name).cloned();
+        if let Some(subscriber) = subscriber {
+            if let Some(message) = subscriber.try_recv()? {
+                return Ok(Some(message));
+            }
+        }
+        let publisher = self.publisher()?;
+        let mut publisher = publisher.lock().map_err(|_| poisoned("publisher"))?;
+        Ok(publisher.get(queue_name)?)
+    }
+}
+
+// Transport backed by in-process queues, used to run the whole pipeline in one process
+#[derive(Default)]
+pub struct InMemoryTransport {
+    queues: Mutex<HashMap<String, VecDeque<String>>>,
+    available: Condvar,
+}
+
+impl InMemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self, queue_name: &str) -> usize {
+        match self.queues.lock() {
+            Ok(queues) => queues.get(queue_name).map_or(0, |queue| queue.len()),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn is_empty(&self, queue_name: &str) -> bool {
+        self.len(queue_name) == 0
+    }
+}
+
+impl MessageTransport for InMemoryTransport {
+    fn send(&self, msg: String, queue_name: &str) -> Result<()> {
+        let mut queues = self.queues.lock().map_err(|_| poisoned("queue"))?;
+        queues.entry(queue_name.to_string()).or_default().push_back(msg);
+        self.available.notify_all();
+        Ok(())
+    }
+
+    fn recv(&self, queue_name: &str) -> Result<String> {
+        let mut queues = self.queues.lock().map_err(|_| poisoned("queue"))?;
+        loop {
+            if let Some(msg) = queues.get_mut(queue_name).and_then(|queue| queue.pop_front()) {
+                return Ok(msg);
+            }
+            queues = self.available.wait(queues).map_err(|_| poisoned("queue"))?;
+        }
+    }
+
+    fn try_recv(&self, queue_name: &str) -> Result<Option<String>> {
+        let mut queues = self.queues.lock().map_err(|_| poisoned("queue"))?;
+        Ok(queues.get_mut(queue_name).and_then(|queue| queue.pop_front()))
+    }
+}
+
+fn poisoned(what: &str) -> Error {
+    Error::Transport(format!("{} lock poisoned", what))
+}
@@ -0,0 +1,248 @@
+use std::fmt;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::config::InventoryConfig;
+use crate::journal::JournalError;
+use crate::replenishment::PurchaseOrder;
+use crate::structs::{Inventory, Order, OrderLine, Reservation};
+
+pub(crate) const PENINSULAR_STATES: [&str; 13] = [
+    "Johor", "Kedah", "Kelantan", "Kuala Lumpur", "Melaka", "Negeri Sembilan", "Pahang",
+    "Penang", "Perak", "Perlis", "Putrajaya", "Selangor", "Terengganu",
+];
+pub(crate) const EAST_MALAYSIA_STATES: [&str; 3] = ["Sabah", "Sarawak", "Labuan"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarehouseConfig {
+    pub name: String,
+    // States whose orders this warehouse ships first
+    pub states: Vec<String>,
+    // Journal file persisting this warehouse's stock; kept in memory only when unset
+    #[serde(default)]
+    pub journal: Option<String>,
+}
+
+// One warehouse in Peninsular and one in East Malaysia
+pub fn default_warehouses() -> Vec<WarehouseConfig> {
+    let warehouse = |name: &str, states: &[&str]| WarehouseConfig {
+        name: name.to_string(),
+        states: states.iter().map(|state| state.to_string()).collect(),
+        journal: None,
+    };
+    vec![warehouse("Shah Alam", &PENINSULAR_STATES), warehouse("Kota Kinabalu", &EAST_MALAYSIA_STATES)]
+}
+
+// How an order is fulfilled when the warehouse serving its address is short
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FulfilmentStrategy {
+    // Only ever ship from the warehouse serving the address
+    Home,
+    // Ship each line whole from the first warehouse that has all of it, home first
+    #[default]
+    Fallback,
+    // Ship each line from as many warehouses as it takes, home first
+    Split,
+}
+
+// Quantity of an order line shipped from one warehouse
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Allocation {
+    pub warehouse: String,
+    pub sku: String,
+    pub quantity: i32,
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} x{} from {}", self.sku, self.quantity, self.warehouse)
+    }
+}
+
+pub struct Warehouse {
+    pub name: String,
+    pub states: Vec<String>,
+    pub inventory: Inventory,
+}
+
+impl Warehouse {
+    pub fn serves(&self, state: &str) -> bool {
+        self.states.iter().any(|served| served == state)
+    }
+}
+
+pub struct Warehouses {
+    pub warehouses: Vec<Warehouse>,
+}
+
+impl Default for Warehouses {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Warehouses {
+    // The default warehouses, each stocked with the whole catalog
+    pub fn new() -> Self {
+        let warehouses = default_warehouses()
+            .into_iter()
+            .map(|config| Warehouse { name: config.name, states: config.states, inventory: Inventory::new() })
+            .collect();
+        Warehouses { warehouses }
+    }
+
+    // The configured warehouses, recovering each from its journal when it has one
+    pub fn from_config(settings: &InventoryConfig) -> Result<Self, JournalError> {
+        let mut warehouses = Vec::with_capacity(settings.warehouses.len());
+        for config in &settings.warehouses {
+            let mut inventory = Inventory::new()
+                .with_reservation_ttl(Duration::from_millis(settings.reservation_ttl_ms))
+                .with_lead_time(Duration::from_millis(settings.lead_time_ms));
+            if let Some(path) = &config.journal {
+                inventory = inventory.with_journal(path)?;
+            }
+            warehouses.push(Warehouse { name: config.name.clone(), states: config.states.clone(), inventory });
+        }
+        Ok(Warehouses { warehouses })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Warehouse> {
+        self.warehouses.iter().find(|warehouse| warehouse.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Warehouse> {
+        self.warehouses.iter_mut().find(|warehouse| warehouse.name == name)
+    }
+
+    // The warehouse serving an address, or the first one for addresses none serves
+    pub fn home(&self, address: &str) -> &Warehouse {
+        self.warehouses.iter().find(|warehouse| warehouse.serves(address)).unwrap_or(&self.warehouses[0])
+    }
+
+    // Warehouses an order may ship from, most preferred first
+    fn candidates(&self, address: &str, strategy: FulfilmentStrategy) -> Vec<&Warehouse> {
+        let home = self.home(address);
+        let mut candidates = vec![home];
+        if strategy != FulfilmentStrategy::Home {
+            candidates.extend(self.warehouses.iter().filter(|warehouse| warehouse.name != home.name));
+        }
+        candidates
+    }
+
+    // Where to ship each line of the order from, or None when the candidate warehouses
+    // cannot cover every line. Lines are placed one at a time, so a line never counts
+    // on stock already given to an earlier one.
+    pub fn allocate(&self, order: &Order, strategy: FulfilmentStrategy) -> Option<Vec<Allocation>> {
+        let candidates = self.candidates(&order.shipping_address.state, strategy);
+        let mut allocations: Vec<Allocation> = Vec::new();
+        let unallocated = |allocations: &[Allocation], warehouse: &Warehouse, sku: &str| {
+            let allocated: i32 = allocations
+                .iter()
+                .filter(|a| a.warehouse == warehouse.name && a.sku == sku)
+                .map(|a| a.quantity)
+                .sum();
+            warehouse.inventory.available(sku) - allocated
+        };
+
+        for line in &order.lines {
+            if strategy != FulfilmentStrategy::Split {
+                let warehouse = candidates.iter().find(|warehouse| unallocated(&allocations, warehouse, &line.sku) >= line.quantity)?;
+                allocations.push(Allocation { warehouse: warehouse.name.clone(), sku: line.sku.clone(), quantity: line.quantity });
+                continue;
+            }
+
+            let mut remaining = line.quantity;
+            for warehouse in &candidates {
+                let quantity = remaining.min(unallocated(&allocations, warehouse, &line.sku));
+                if quantity > 0 {
+                    allocations.push(Allocation { warehouse: warehouse.name.clone(), sku: line.sku.clone(), quantity });
+                    remaining -= quantity;
+                }
+                if remaining == 0 {
+                    break;
+                }
+            }
+            if remaining > 0 {
+                return None;
+            }
+        }
+        Some(allocations)
+    }
+
+    // The first line of the order that can never be filled under the strategy, even
+    // once every candidate warehouse is back up to its order-up-to level
+    pub fn unfillable_line<'a>(&self, order: &'a Order, strategy: FulfilmentStrategy) -> Option<&'a OrderLine> {
+        let candidates = self.candidates(&order.shipping_address.state, strategy);
+        order.lines.iter().find(|line| {
+            let levels = candidates.iter().map(|warehouse| {
+                warehouse.inventory.stocks.iter().find(|stock| stock.sku == line.sku).map_or(0, |stock| stock.order_up_to)
+            });
+            let most = match strategy {
+                FulfilmentStrategy::Split => levels.sum(),
+                _ => levels.max().unwrap_or(0),
+            };
+            order.quantity_of(&line.sku) > most
+        })
+    }
+
+    pub fn available(&self, item: &str) -> i32 {
+        self.warehouses.iter().map(|warehouse| warehouse.inventory.available(item)).sum()
+    }
+
+    // Reserve every allocation of an order, or none of them
+    pub fn reserve(&mut self, order_id: i32, allocations: &[Allocation]) -> Result<bool, JournalError> {
+        for allocation in allocations {
+            let reserved = match self.get_mut(&allocation.warehouse) {
+                Some(warehouse) => warehouse.inventory.reserve(order_id, &allocation.sku, allocation.quantity)?,
+                None => false,
+            };
+            if !reserved {
+                self.release(order_id)?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Commit the order's reservations in every warehouse holding one
+    pub fn commit(&mut self, order_id: i32) -> Result<Vec<(String, Reservation)>, JournalError> {
+        let mut committed = Vec::new();
+        for warehouse in &mut self.warehouses {
+            if let Some(reservation) = warehouse.inventory.commit(order_id)? {
+                committed.push((warehouse.name.clone(), reservation));
+            }
+        }
+        Ok(committed)
+    }
+
+    // Release the order's reservations in every warehouse holding one
+    pub fn release(&mut self, order_id: i32) -> Result<Vec<(String, Reservation)>, JournalError> {
+        let mut released = Vec::new();
+        for warehouse in &mut self.warehouses {
+            if let Some(reservation) = warehouse.inventory.release(order_id)? {
+                released.push((warehouse.name.clone(), reservation));
+            }
+        }
+        Ok(released)
+    }
+
+    pub fn expire(&mut self) -> Result<Vec<(String, i32, Reservation)>, JournalError> {
+        let mut expired = Vec::new();
+        for warehouse in &mut self.warehouses {
+            for (order_id, reservation) in warehouse.inventory.expire()? {
+                expired.push((warehouse.name.clone(), order_id, reservation));
+            }
+        }
+        Ok(expired)
+    }
+
+    pub fn receive_due(&mut self) -> Result<Vec<(String, PurchaseOrder)>, JournalError> {
+        let mut received = Vec::new();
+        for warehouse in &mut self.warehouses {
+            for po in warehouse.inventory.receive_due()? {
+                received.push((warehouse.name.clone(), po));
+            }
+        }
+        Ok(received)
+    }
+}